@@ -1,9 +1,19 @@
+use std::env;
 use std::error::Error;
+use std::path::PathBuf;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    tonic_build::compile_protos("proto/auth.proto")?;
-    tonic_build::compile_protos("proto/product.proto")?;
-    tonic_build::compile_protos("proto/order.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    // The descriptor set carries the google.api.http annotations the REST routes are transcoded from
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("gateway_descriptor.bin"))
+        .compile_protos(&["proto/auth.proto", "proto/product.proto", "proto/order.proto"], &["proto"])?;
+
+    // Client of the backends' server reflection, dynamic backends are discovered through it
+    tonic_build::configure()
+        .build_server(false)
+        .compile_protos(&["proto/grpc/reflection/v1/reflection.proto"], &["proto"])?;
 
     Ok(())
-}
\ No newline at end of file
+}
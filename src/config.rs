@@ -0,0 +1,293 @@
+//! Optional gateway configuration file, reloaded while the gateway is running
+
+use crate::error::Error;
+use ipnet::IpNet;
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GatewayConfig {
+    /// CIDR rules keyed by the route group they are attached to in `routes::init_routes`
+    #[serde(default)]
+    pub ip_filters: HashMap<String, IpFilterConfig>,
+
+    /// CORS policies keyed by route group (`auth`, `products`, `orders`), only read at startup
+    #[serde(default)]
+    pub cors: HashMap<String, CorsGroupConfig>,
+
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConfig,
+
+    /// Certificates of the HTTPS listener on `GATEWAY_TLS_ADDRS`, files are watched, the section itself is only read at startup
+    pub tls: Option<TlsConfig>,
+
+    /// Connection settings of the backend services keyed by `auth`, `product` and `order`, only read at startup
+    #[serde(default)]
+    pub backends: HashMap<String, BackendSettings>,
+
+    /// Partner principals keyed by an identity of their client certificate (URI, DNS or email SAN, or subject CN)
+    #[serde(default)]
+    pub client_principals: HashMap<String, ClientPrincipalConfig>,
+
+    /// Maximum JSON body size in bytes keyed by route pattern, e.g. `/orders`, only read at startup
+    #[serde(default)]
+    pub body_limits: HashMap<String, usize>,
+
+    /// Only read at startup
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+
+    /// `Cache-Control` and `Last-Modified` of GET responses keyed by route pattern, e.g. `/products`
+    #[serde(default)]
+    pub cache_headers: HashMap<String, CacheHeadersConfig>,
+
+    /// Only read at startup
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Backends without compiled clients keyed by name, their RPCs are discovered at startup, only read at startup
+    #[serde(default)]
+    pub dynamic_backends: HashMap<String, DynamicBackendConfig>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct IpFilterConfig {
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CorsGroupConfig {
+    /// Overrides the origins from `GATEWAY_CORS_ORIGIN` for the group
+    pub origins: Option<Vec<String>>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub expose_headers: Option<Vec<String>>,
+    #[serde(default)]
+    pub supports_credentials: bool,
+    pub max_age: Option<usize>,
+}
+
+/// Header values by lowercase name, an empty value removes a default header
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SecurityHeadersConfig {
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Overrides keyed by route pattern, e.g. `/products/{id}`
+    #[serde(default)]
+    pub routes: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub certificates: Vec<TlsCertificateConfig>,
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of CAs client certificates have to be issued by
+    pub ca: PathBuf,
+    /// Rejects handshakes without a client certificate, otherwise such clients fall back to JWT
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientPrincipalConfig {
+    /// Becomes the `sub` claim, the user id of the partner
+    pub name: String,
+    /// Becomes the `company` claim
+    #[serde(default)]
+    pub company: String,
+    /// Route groups (`auth`, `products`, `orders`) the partner may call
+    #[serde(default)]
+    pub routes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsCertificateConfig {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// SNI names served with this certificate, `*.example.com` matches one subdomain level
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    /// Served to clients without SNI or with an unknown name, defaults to the first certificate
+    #[serde(default)]
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Responses kept across all routes and tenants, the least recently used one is evicted first
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    /// Seconds a GET response is cached keyed by route pattern, routes without a TTL are not cached
+    #[serde(default = "default_cache_ttls")]
+    pub ttls: HashMap<String, u64>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        ResponseCacheConfig {
+            max_entries: default_cache_max_entries(),
+            ttls: default_cache_ttls(),
+        }
+    }
+}
+
+fn default_cache_max_entries() -> usize {
+    1000
+}
+
+fn default_cache_ttls() -> HashMap<String, u64> {
+    HashMap::from([("/products".to_owned(), 30), ("/products/{id}".to_owned(), 30)])
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CacheHeadersConfig {
+    /// Defaults to `private, no-cache`, clients revalidate with the ETag before every use
+    pub cache_control: Option<String>,
+    /// Sends the time the response body last changed, as observed by the gateway
+    #[serde(default)]
+    pub last_modified: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+    /// Encodings offered to clients in order of preference, `br`, `zstd` and `gzip`, empty disables compression
+    #[serde(default = "default_compression_encodings")]
+    pub encodings: Vec<String>,
+    /// Bodies smaller than this many bytes are sent as they are
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: usize,
+    /// Content types sent as they are, `image/*` matches all subtypes and a trailing `*` any suffix
+    #[serde(default = "default_compression_excluded")]
+    pub excluded_content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            encodings: default_compression_encodings(),
+            min_size: default_compression_min_size(),
+            excluded_content_types: default_compression_excluded(),
+        }
+    }
+}
+
+fn default_compression_encodings() -> Vec<String> {
+    vec!["br".to_owned(), "zstd".to_owned(), "gzip".to_owned()]
+}
+
+fn default_compression_min_size() -> usize {
+    1024
+}
+
+fn default_compression_excluded() -> Vec<String> {
+    ["image/*", "video/*", "audio/*", "application/zip", "application/gzip", "application/grpc*"]
+        .map(str::to_owned)
+        .to_vec()
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct BackendSettings {
+    pub tls: Option<BackendTlsConfig>,
+    /// Compression of the messages sent to the backend, responses in gzip and zstd are always accepted
+    pub compression: Option<GrpcCompression>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DynamicBackendConfig {
+    pub endpoint: String,
+    /// Descriptor set with the imports included (`protoc --include_imports --descriptor_set_out`),
+    /// the services are asked for through gRPC server reflection without it
+    pub descriptor_set: Option<PathBuf>,
+    #[serde(flatten)]
+    pub settings: BackendSettings,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GrpcCompression {
+    Gzip,
+    Zstd,
+}
+
+/// Files are watched, the backend channel is rebuilt when any of them changes
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct BackendTlsConfig {
+    /// PEM bundle of CAs trusted for the backend certificate, required as system roots are not used
+    pub ca: Option<PathBuf>,
+    /// PEM client certificate presented to the backend
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// Name verified against the backend certificate instead of the endpoint host
+    pub server_name: Option<String>,
+}
+
+impl BackendTlsConfig {
+    pub fn paths(&self) -> Vec<PathBuf> {
+        [&self.ca, &self.cert, &self.key].into_iter().flatten().cloned().collect()
+    }
+}
+
+impl GatewayConfig {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+
+        toml::from_str(&content).map_err(|e| Error::Config { path: path.display().to_string(), source: e })
+    }
+}
+
+/// Polls modification times of the files and calls `on_change` once any of them changed.
+pub fn watch_files<F>(paths: Vec<PathBuf>, interval: Duration, on_change: F)
+where
+    F: Fn() + Send + 'static,
+{
+    let modified = |paths: &[PathBuf]| paths.iter()
+        .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect::<Vec<Option<SystemTime>>>();
+
+    thread::spawn(move || {
+        let mut last = modified(&paths);
+        loop {
+            thread::sleep(interval);
+            let current = modified(&paths);
+            if current != last {
+                info!("change detected in {:?}", paths);
+                last = current;
+                on_change();
+            }
+        }
+    });
+}
+
+/// Loads the config file and keeps applying it with `apply` whenever the file changes.
+pub fn load_and_watch<F>(path: PathBuf, interval: Duration, apply: F) -> Result<GatewayConfig, Error>
+where
+    F: Fn(&GatewayConfig) + Send + 'static,
+{
+    let config = GatewayConfig::load(&path)?;
+    apply(&config);
+
+    watch_files(vec![path.clone()], interval, move || match GatewayConfig::load(&path) {
+        Ok(config) => {
+            info!("reloaded config from {}", path.display());
+            apply(&config);
+        }
+        Err(e) => error!("keeping previous config, reload failed: {}", e),
+    });
+
+    Ok(config)
+}
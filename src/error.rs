@@ -1,5 +1,12 @@
 //! Main Crate Error
 
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use crate::middleware::request_id;
+use crate::models::error_models::ErrorResponse;
+use std::collections::BTreeMap;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 
@@ -26,9 +33,80 @@ pub enum Error {
         source: std::env::VarError,
     },
 
+    #[error("Invalid value of variable {input}: {value}")]
+    InvalidVar {
+        input: &'static str,
+        value: String,
+    },
+
+    #[error("Can not parse config file {path}")]
+    Config {
+        path: String,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("Missing config: {0}")]
+    MissingConfig(&'static str),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Can not load certificate {path}: {message}")]
+    Certificate {
+        path: String,
+        message: String,
+    },
+
+    #[error("Can not connect to {backend} backend at {endpoint}: {message}")]
+    BackendConnect {
+        backend: String,
+        endpoint: String,
+        message: String,
+    },
+
+    #[error("Invalid protobuf descriptors: {0}")]
+    Descriptor(String),
+
+    #[error("Amount in {0} overflows")]
+    AmountOverflow(String),
+
+    #[error("Can not combine amounts in {0} and {1}")]
+    CurrencyMismatch(String, String),
+
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+
     #[error(transparent)]
     InvalidUrl(#[from] tonic::codegen::http::uri::InvalidUri),
 
     #[error("toml file read error occurred: {0}")]
     InfluxdbHttpRequest(#[from] reqwest::Error),
-}
\ No newline at end of file
+}
+/// Builds an HTTP error with a JSON body carrying the id of the current request.
+pub fn api_error(status: StatusCode, message: impl Into<String>) -> actix_web::Error {
+    error_response(status, message.into(), None)
+}
+
+/// Builds a 400 error listing the validation messages of every invalid field.
+pub fn validation_error(message: &str, fields: BTreeMap<String, Vec<String>>) -> actix_web::Error {
+    error_response(StatusCode::BAD_REQUEST, message.to_owned(), Some(fields))
+}
+
+/// Builds a 409 error listing the conflicting fields, e.g. line items which do not match the catalogue.
+pub fn conflict_error(message: String, fields: BTreeMap<String, Vec<String>>) -> actix_web::Error {
+    error_response(StatusCode::CONFLICT, message, Some(fields))
+}
+
+fn error_response(status: StatusCode, message: String, fields: Option<BTreeMap<String, Vec<String>>>) -> actix_web::Error {
+    let body = ErrorResponse {
+        error: message.clone(),
+        request_id: request_id::current(),
+        fields,
+    };
+
+    InternalError::from_response(message, HttpResponse::build(status).json(body)).into()
+}
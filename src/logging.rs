@@ -0,0 +1,144 @@
+use crate::error::Error;
+use crate::middleware::request_id;
+use chrono::{SecondsFormat, Utc};
+use env_logger::fmt::Formatter;
+use log::Record;
+use regex::{Captures, Regex};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+
+pub const DEFAULT_REDACTED_FIELDS: &str = "password,token,secret,authorization,api_key";
+
+const MASK: &str = "***";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct LogConfig {
+    pub format: LogFormat,
+    pub redact: bool,
+    pub redacted_fields: Vec<String>,
+}
+
+pub fn init(config: LogConfig) -> Result<(), Error> {
+    let redactor = if config.redact {
+        Some(Redactor::new(&config.redacted_fields)?)
+    } else {
+        None
+    };
+    let format = config.format;
+
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| {
+            let message = record.args().to_string();
+            let message = match &redactor {
+                Some(redactor) => redactor.redact(&message),
+                None => Cow::Borrowed(message.as_str()),
+            };
+            match format {
+                LogFormat::Text => format_text(buf, record, &message),
+                LogFormat::Json => format_json(buf, record, &message),
+            }
+        })
+        .init();
+
+    Ok(())
+}
+
+// Same layout as the default env_logger format, with the request id appended when there is one
+fn format_text(buf: &mut Formatter, record: &Record, message: &str) -> std::io::Result<()> {
+    let timestamp = buf.timestamp();
+    match request_id::current() {
+        Some(id) => writeln!(buf, "[{} {:<5} {} request_id={}] {}", timestamp, record.level(), record.target(), id, message),
+        None => writeln!(buf, "[{} {:<5} {}] {}", timestamp, record.level(), record.target(), message),
+    }
+}
+
+fn format_json(buf: &mut Formatter, record: &Record, message: &str) -> std::io::Result<()> {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_owned(), json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert("level".to_owned(), json!(record.level().as_str()));
+    fields.insert("target".to_owned(), json!(record.target()));
+    fields.insert("message".to_owned(), json!(message));
+
+    request_id::with_context(|ctx| {
+        fields.insert("request_id".to_owned(), json!(ctx.request_id));
+        fields.insert("route".to_owned(), json!(ctx.route));
+        if let Some(user_id) = ctx.user_id() {
+            fields.insert("user_id".to_owned(), json!(user_id));
+        }
+        fields.insert("latency_ms".to_owned(), json!(ctx.started.elapsed().as_millis() as u64));
+    });
+
+    writeln!(buf, "{}", Value::Object(fields))
+}
+
+/// Masks emails, bearer tokens, JWTs and values of sensitive fields in log messages.
+pub struct Redactor {
+    email: Regex,
+    bearer: Regex,
+    jwt: Regex,
+    fields: Option<Regex>,
+}
+
+impl Redactor {
+    pub fn new(fields: &[String]) -> Result<Self, Error> {
+        let fields = fields.iter()
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .map(regex::escape)
+            .collect::<Vec<_>>();
+
+        // Matches `field=value`, `field: value` and `"field":"value"` forms
+        let fields = if fields.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&format!(r#"(?i)("?\b(?:{})"?\s*[:=]\s*"?)([^"\s,;&}}]+)"#, fields.join("|")))?)
+        };
+
+        Ok(Redactor {
+            email: Regex::new(r"([A-Za-z0-9._%+-])[A-Za-z0-9._%+-]*@([A-Za-z0-9.-]+\.[A-Za-z]{2,})")?,
+            bearer: Regex::new(r"(?i)\b(bearer|token)\s+[A-Za-z0-9._~+/=-]+")?,
+            jwt: Regex::new(r"\beyJ[A-Za-z0-9_-]*\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*")?,
+            fields,
+        })
+    }
+
+    pub fn redact<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        let mut message = Cow::Borrowed(message);
+
+        if let Some(fields) = &self.fields {
+            if let Cow::Owned(s) = fields.replace_all(&message, |c: &Captures| format!("{}{}", &c[1], MASK)) {
+                message = Cow::Owned(s);
+            }
+        }
+        if let Cow::Owned(s) = self.bearer.replace_all(&message, |c: &Captures| format!("{} {}", &c[1], MASK)) {
+            message = Cow::Owned(s);
+        }
+        if let Cow::Owned(s) = self.jwt.replace_all(&message, MASK) {
+            message = Cow::Owned(s);
+        }
+        if let Cow::Owned(s) = self.email.replace_all(&message, |c: &Captures| format!("{}{}@{}", &c[1], MASK, &c[2])) {
+            message = Cow::Owned(s);
+        }
+
+        message
+    }
+}
@@ -1,3 +1,6 @@
+// `Error` carries a whole `tonic::Status`, results of the crate are large on purpose
+#![allow(clippy::result_large_err)]
+
 extern crate core;
 
 mod error;
@@ -5,22 +8,48 @@ mod routes;
 mod services;
 mod models;
 mod middleware;
+mod logging;
+mod config;
+mod tls;
+mod validation;
 
 use crate::error::Error;
+use crate::logging::{LogConfig, LogFormat, DEFAULT_REDACTED_FIELDS};
+use crate::middleware::access_log::{AccessLog, AccessLogFormat, AccessLogger, RotatingFile};
+use crate::middleware::trusted_proxies::{parse_proxy, TrustedProxies};
+use crate::config::{GatewayConfig, SecurityHeadersConfig};
+use crate::middleware::client_cert::{self, ClientPrincipals};
+use crate::middleware::idempotency::IdempotencyStore;
+use crate::middleware::cors::{CorsPolicies, OriginPattern};
+use crate::middleware::ip_filter::IpFilters;
+use crate::middleware::metrics::MetricsPublisher;
+use crate::middleware::response_cache::ResponseCache;
+use crate::middleware::conditional_get::CacheHeaders;
+use crate::middleware::compression::{Compression, CompressionPolicy};
+use crate::middleware::request_id::RequestIdMiddleware;
+use crate::tls::CertResolver;
+use crate::middleware::security_headers::{SecurityHeaders, SecurityHeadersMiddleware};
+use crate::services::BackendConfig;
 use crate::services::auth_service::AuthService;
 use crate::services::order_service::OrderService;
 use crate::services::product_service::ProductService;
-use actix_cors::Cors;
+use crate::services::transcoding_service::{DescriptorSource, TranscodingService};
 use actix_web::{web, App, HttpServer};
 use reqwest::Client;
-use routes::init_routes;
+use routes::{check_bindings, init_routes, RouteState};
+use std::collections::HashMap;
 use std::env;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 const SECRET_NAME: &str = "AUTH_SECRET";
 
 const GATEWAY_ADDR: &str = "GATEWAY_ADDRS";
 
+const GATEWAY_TLS_ADDR: &str = "GATEWAY_TLS_ADDRS";
+
 const GATEWAY_CORS_ORIGIN: &str = "GATEWAY_CORS_ORIGIN";
 
 const AUTH_ENDPOINT: &str = "AUTH_ENDPOINT";
@@ -37,11 +66,52 @@ const INFLUXDB_ORG: &str = "INFLUXDB_ORG";
 
 const INFLUXDB_BUCKET: &str = "INFLUXDB_BUCKET";
 
+const LOG_FORMAT: &str = "LOG_FORMAT";
+
+const LOG_REDACT: &str = "LOG_REDACT";
+
+const LOG_REDACT_FIELDS: &str = "LOG_REDACT_FIELDS";
+
+const ACCESS_LOG_FORMAT: &str = "ACCESS_LOG_FORMAT";
+
+const ACCESS_LOG_FILE: &str = "ACCESS_LOG_FILE";
+
+const ACCESS_LOG_MAX_SIZE: &str = "ACCESS_LOG_MAX_SIZE";
+
+const ACCESS_LOG_MAX_FILES: &str = "ACCESS_LOG_MAX_FILES";
+
+const TRUSTED_PROXIES: &str = "TRUSTED_PROXIES";
+
+const GATEWAY_CONFIG: &str = "GATEWAY_CONFIG";
+
+const GATEWAY_CONFIG_RELOAD_INTERVAL: &str = "GATEWAY_CONFIG_RELOAD_INTERVAL";
+
+const IDEMPOTENCY_TTL: &str = "IDEMPOTENCY_TTL";
+
+const IDEMPOTENCY_MAX_KEYS: &str = "IDEMPOTENCY_MAX_KEYS";
+
+const GRPC_WEB_PREFIX: &str = "GRPC_WEB_PREFIX";
+
 #[actix_web::main]
 async fn main() -> Result<(), Error> {
     env::set_var("RUST_LOG", env::var("RUST_LOG").unwrap_or("info".to_owned()));
     env::set_var("RUST_BACKTRACE", "1");
-    env_logger::init();
+
+    let log_format = env::var(LOG_FORMAT).unwrap_or("text".to_owned());
+    let log_format = log_format.parse::<LogFormat>()
+        .map_err(|_| Error::InvalidVar { input: LOG_FORMAT, value: log_format })?;
+
+    let log_redact = env::var(LOG_REDACT).unwrap_or("true".to_owned());
+    let log_redact = log_redact.parse::<bool>()
+        .map_err(|_| Error::InvalidVar { input: LOG_REDACT, value: log_redact })?;
+
+    let log_redact_fields = env::var(LOG_REDACT_FIELDS).unwrap_or(DEFAULT_REDACTED_FIELDS.to_owned());
+
+    logging::init(LogConfig {
+        format: log_format,
+        redact: log_redact,
+        redacted_fields: log_redact_fields.split(',').map(String::from).collect(),
+    })?;
 
     let secret = Arc::new(env::var(SECRET_NAME)
         .map_err(|e| Error::Var { input: SECRET_NAME, source: e })?);
@@ -49,8 +119,12 @@ async fn main() -> Result<(), Error> {
     let addrs = env::var(GATEWAY_ADDR)
         .map_err(|e| Error::Var { input: GATEWAY_ADDR, source: e })?;
 
-    let cors_origin = Arc::new(env::var(GATEWAY_CORS_ORIGIN)
-        .map_err(|e| Error::Var { input: GATEWAY_CORS_ORIGIN, source: e })?);
+    let cors_origin = env::var(GATEWAY_CORS_ORIGIN)
+        .map_err(|e| Error::Var { input: GATEWAY_CORS_ORIGIN, source: e })?;
+    let cors_origins = cors_origin.split(',')
+        .filter(|o| !o.trim().is_empty())
+        .map(|o| OriginPattern::parse(o).ok_or_else(|| Error::InvalidVar { input: GATEWAY_CORS_ORIGIN, value: o.to_owned() }))
+        .collect::<Result<Vec<_>, _>>()?;
 
     let auth_endpoint = env::var(AUTH_ENDPOINT)
         .map_err(|e| Error::Var { input: AUTH_ENDPOINT, source: e })?;
@@ -73,36 +147,180 @@ async fn main() -> Result<(), Error> {
     let influxdb_bucket = Arc::new(env::var(INFLUXDB_BUCKET)
         .map_err(|e| Error::Var { input: INFLUXDB_BUCKET, source: e })?);
 
-    let auth_service = AuthService::new(auth_endpoint).await?;
+    let access_log_format = env::var(ACCESS_LOG_FORMAT).unwrap_or("combined".to_owned());
+    let access_log_format = access_log_format.parse::<AccessLogFormat>()
+        .map_err(|_| Error::InvalidVar { input: ACCESS_LOG_FORMAT, value: access_log_format })?;
+
+    let access_log_file = match env::var(ACCESS_LOG_FILE) {
+        Ok(path) => {
+            let max_size = env::var(ACCESS_LOG_MAX_SIZE).unwrap_or("10485760".to_owned());
+            let max_size = max_size.parse::<u64>()
+                .map_err(|_| Error::InvalidVar { input: ACCESS_LOG_MAX_SIZE, value: max_size })?;
+
+            let max_files = env::var(ACCESS_LOG_MAX_FILES).unwrap_or("5".to_owned());
+            let max_files = max_files.parse::<usize>()
+                .map_err(|_| Error::InvalidVar { input: ACCESS_LOG_MAX_FILES, value: max_files })?;
+
+            Some(RotatingFile::open(PathBuf::from(path), max_size, max_files)?)
+        }
+        Err(_) => None,
+    };
+
+    let access_logger = Arc::new(AccessLogger::new(access_log_format, access_log_file)?);
+
+    let trusted_proxies = env::var(TRUSTED_PROXIES).unwrap_or_default();
+    let trusted_proxies = Arc::new(trusted_proxies.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| parse_proxy(p).ok_or_else(|| Error::InvalidVar { input: TRUSTED_PROXIES, value: p.to_owned() }))
+        .collect::<Result<Vec<_>, _>>()?);
 
-    let product_service = ProductService::new(product_endpoint).await?;
+    let ip_filters = Arc::new(IpFilters::default());
 
-    let order_service = OrderService::new(order_endpoint).await?;
+    let security_headers = Arc::new(SecurityHeaders::new(&SecurityHeadersConfig::default()));
 
-    let client = Arc::new(Client::new());
+    let client_principals = Arc::new(ClientPrincipals::default());
 
-    HttpServer::new(move || {
+    let cache_headers = Arc::new(CacheHeaders::new(&HashMap::new()));
+
+    let idempotency_ttl = env::var(IDEMPOTENCY_TTL).unwrap_or("86400".to_owned());
+    let idempotency_ttl = idempotency_ttl.parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|_| Error::InvalidVar { input: IDEMPOTENCY_TTL, value: idempotency_ttl })?;
+
+    let idempotency_max_keys = env::var(IDEMPOTENCY_MAX_KEYS).unwrap_or("100000".to_owned());
+    let idempotency_max_keys = idempotency_max_keys.parse::<NonZeroUsize>()
+        .map_err(|_| Error::InvalidVar { input: IDEMPOTENCY_MAX_KEYS, value: idempotency_max_keys })?;
+
+    let grpc_web_prefix = match env::var(GRPC_WEB_PREFIX) {
+        Ok(prefix) if prefix.starts_with('/') => Some(prefix.trim_end_matches('/').to_owned()),
+        Ok(prefix) => return Err(Error::InvalidVar { input: GRPC_WEB_PREFIX, value: prefix }),
+        Err(_) => None,
+    };
+
+    let reload_interval = env::var(GATEWAY_CONFIG_RELOAD_INTERVAL).unwrap_or("10".to_owned());
+    let reload_interval = reload_interval.parse::<u64>()
+        .map_err(|_| Error::InvalidVar { input: GATEWAY_CONFIG_RELOAD_INTERVAL, value: reload_interval })?;
+
+    let gateway_config = match env::var(GATEWAY_CONFIG) {
+        Ok(config_path) => {
+            let ip_filters = Arc::clone(&ip_filters);
+            let security_headers = Arc::clone(&security_headers);
+            let client_principals = Arc::clone(&client_principals);
+            let cache_headers = Arc::clone(&cache_headers);
+            config::load_and_watch(PathBuf::from(config_path), Duration::from_secs(reload_interval), move |config| {
+                ip_filters.replace(config.ip_filters.clone());
+                security_headers.replace(&config.security_headers);
+                client_principals.replace(config.client_principals.clone());
+                cache_headers.replace(&config.cache_headers);
+            })?
+        }
+        Err(_) => GatewayConfig::default(),
+    };
+
+    let tls_config = match env::var(GATEWAY_TLS_ADDR) {
+        Ok(tls_addrs) => {
+            let tls_settings = gateway_config.tls.clone().unwrap_or_default();
+            if tls_settings.certificates.is_empty() {
+                return Err(Error::MissingConfig("GATEWAY_TLS_ADDRS requires [[tls.certificates]] in GATEWAY_CONFIG"));
+            }
+
+            let resolver = Arc::new(CertResolver::new(tls_settings.certificates)?);
+            tls::watch_certificates(Arc::clone(&resolver), Duration::from_secs(reload_interval));
+
+            Some((tls_addrs, tls::server_config(resolver, tls_settings.client_auth.as_ref())?))
+        }
+        Err(_) => None,
+    };
+
+    let backend = |name: &str, endpoint: String| BackendConfig {
+        name: name.to_owned(),
+        endpoint,
+        tls: gateway_config.backends.get(name).and_then(|b| b.tls.clone()),
+        compression: gateway_config.backends.get(name).and_then(|b| b.compression),
+    };
+
+    let reload_interval = Duration::from_secs(reload_interval);
+
+    let metrics = Arc::new(MetricsPublisher::new(Arc::new(Client::new()), influxdb_token, influxdb_url, influxdb_org, influxdb_bucket));
+
+    let auth_backend = backend("auth", auth_endpoint);
+    let auth_channel = auth_backend.connect_shared(reload_interval).await?;
+    let auth_service = AuthService::new(&auth_backend, Arc::clone(&auth_channel));
+
+    let product_backend = backend("product", product_endpoint);
+    let product_channel = product_backend.connect_shared(reload_interval).await?;
+    let product_service = ProductService::new(&product_backend, Arc::clone(&product_channel), Arc::clone(&metrics));
+
+    let order_backend = backend("order", order_endpoint);
+    let order_channel = order_backend.connect_shared(reload_interval).await?;
+    let order_service = OrderService::new(&order_backend, Arc::clone(&order_channel), Arc::clone(&metrics));
+
+    let mut transcoded_backends = vec![
+        (auth_backend, auth_channel, DescriptorSource::Compiled),
+        (product_backend, product_channel, DescriptorSource::Compiled),
+        (order_backend, order_channel, DescriptorSource::Compiled),
+    ];
+    for (name, dynamic) in &gateway_config.dynamic_backends {
+        let backend = BackendConfig {
+            name: name.clone(),
+            endpoint: dynamic.endpoint.clone(),
+            tls: dynamic.settings.tls.clone(),
+            compression: dynamic.settings.compression,
+        };
+        let source = match &dynamic.descriptor_set {
+            Some(path) => DescriptorSource::File(path.clone()),
+            None => DescriptorSource::Reflection,
+        };
+        let channel = backend.connect_shared(reload_interval).await?;
+        transcoded_backends.push((backend, channel, source));
+    }
+
+    let transcoding_service = TranscodingService::new(transcoded_backends).await?;
+
+    let http_bindings = transcoding_service.http_bindings()?;
+    check_bindings(&http_bindings)?;
+
+    let backend_rpcs = transcoding_service.rpcs();
+
+    let route_state = RouteState {
+        secret,
+        metrics,
+        ip_filters,
+        cors: Arc::new(CorsPolicies::new(cors_origins, gateway_config.cors)?),
+        principals: client_principals,
+        body_limits: Arc::new(gateway_config.body_limits),
+        idempotency: Arc::new(IdempotencyStore::new(idempotency_ttl, idempotency_max_keys)),
+        response_cache: Arc::new(ResponseCache::new(&gateway_config.response_cache)),
+        cache_headers,
+        http_bindings: Arc::new(http_bindings),
+        grpc_web_prefix,
+        backend_rpcs: Arc::new(backend_rpcs),
+    };
+
+    let compression = Arc::new(CompressionPolicy::new(&gateway_config.compression));
+
+    let mut server = HttpServer::new(move || {
         App::new()
-            .wrap(
-                Cors::default()
-                    .allowed_origin(&cors_origin)
-                    .allow_any_method()
-                    .allow_any_header())
+            .wrap(Compression::new(Arc::clone(&compression)))
+            .wrap(SecurityHeadersMiddleware::new(Arc::clone(&security_headers)))
+            .wrap(AccessLog::new(Arc::clone(&access_logger)))
+            .wrap(TrustedProxies::new(Arc::clone(&trusted_proxies)))
+            .wrap(RequestIdMiddleware)
             .app_data(web::Data::new(auth_service.clone()))
             .app_data(web::Data::new(product_service.clone()))
             .app_data(web::Data::new(order_service.clone()))
-            .configure(|cfg| init_routes(cfg,
-                                         Arc::clone(&secret),
-                                         Arc::clone(&client),
-                                         Arc::clone(&influxdb_token),
-                                         Arc::clone(&influxdb_url),
-                                         Arc::clone(&influxdb_org),
-                                         Arc::clone(&influxdb_bucket))
-            )
+            .app_data(web::Data::new(transcoding_service.clone()))
+            .configure(|cfg| init_routes(cfg, &route_state))
     })
-    .bind(addrs)?
-    .run()
-    .await?;
+    .on_connect(client_cert::on_connect)
+    .bind(addrs)?;
+
+    if let Some((tls_addrs, tls_config)) = tls_config {
+        server = server.bind_rustls_0_23(tls_addrs, tls_config)?;
+    }
+
+    server.run().await?;
 
     Ok(())
 }
@@ -1,2 +1,14 @@
 pub mod jwt_validator;
-pub mod metrics;
\ No newline at end of file
+pub mod metrics;
+pub mod request_id;
+pub mod access_log;
+pub mod trusted_proxies;
+pub mod ip_filter;
+pub mod cors;
+pub mod security_headers;
+pub mod client_cert;
+pub mod idempotency;
+pub mod stored_response;
+pub mod response_cache;
+pub mod conditional_get;
+pub mod compression;
\ No newline at end of file
@@ -0,0 +1,393 @@
+use crate::error::Error as AppError;
+use crate::middleware::request_id;
+use crate::middleware::trusted_proxies::ClientInfo;
+use actix_service::{Service, Transform};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use chrono::{Local, SecondsFormat, Utc};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use log::{error, info};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Instant;
+
+// Lines waiting for the writer thread, further lines are dropped while the file can not keep up
+const FILE_QUEUE_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Combined,
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Writes access log lines either through the `access_log` log target or to a size rotated file.
+pub struct AccessLogger {
+    format: AccessLogFormat,
+    // Lines for the thread owning the file, writes and rotations block and must not run on the actix workers
+    file: Option<SyncSender<String>>,
+}
+
+impl AccessLogger {
+    pub fn new(format: AccessLogFormat, file: Option<RotatingFile>) -> Result<Self, AppError> {
+        let file = match file {
+            Some(mut file) => {
+                let (sender, receiver) = mpsc::sync_channel::<String>(FILE_QUEUE_SIZE);
+                thread::Builder::new().name("access-log".to_owned()).spawn(move || {
+                    for line in receiver {
+                        if let Err(e) = file.write_line(&line) {
+                            error!("Failed to write access log: {}", e);
+                        }
+                    }
+                })?;
+                Some(sender)
+            }
+            None => None,
+        };
+
+        Ok(AccessLogger { format, file })
+    }
+
+    fn write(&self, entry: &AccessLogEntry) {
+        let line = match self.format {
+            AccessLogFormat::Combined => entry.to_combined(),
+            AccessLogFormat::Json => entry.to_json(),
+        };
+
+        match &self.file {
+            Some(file) => match file.try_send(line) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => error!("Failed to write access log: queue is full"),
+                Err(TrySendError::Disconnected(_)) => error!("Failed to write access log: writer thread stopped"),
+            },
+            None => info!(target: "access_log", "{}", line),
+        }
+    }
+}
+
+/// Log file rotated by size: `access.log` becomes `access.log.1`, `access.log.1` becomes `access.log.2` and so on.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self, AppError> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile { path, max_size, max_files, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                let from = self.rotated_path(i);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(i + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+struct AccessLogEntry {
+    client_ip: Option<IpAddr>,
+    method: String,
+    path: String,
+    route: String,
+    version: String,
+    status: u16,
+    bytes_in: u64,
+    bytes_out: u64,
+    latency_ms: u128,
+    user_id: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    request_id: Option<String>,
+}
+
+impl AccessLogEntry {
+    // Apache combined format followed by bytes in and latency
+    fn to_combined(&self) -> String {
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {} {}ms",
+            self.client_ip.map(|ip| ip.to_string()).unwrap_or("-".to_owned()),
+            self.user_id.as_deref().unwrap_or("-"),
+            Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            self.bytes_out,
+            self.referer.as_deref().unwrap_or("-"),
+            self.user_agent.as_deref().unwrap_or("-"),
+            self.bytes_in,
+            self.latency_ms,
+        )
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "client_ip": self.client_ip,
+            "method": self.method,
+            "path": self.path,
+            "route": self.route,
+            "protocol": self.version,
+            "status": self.status,
+            "bytes_in": self.bytes_in,
+            "bytes_out": self.bytes_out,
+            "latency_ms": self.latency_ms as u64,
+            "user_id": self.user_id,
+            "user_agent": self.user_agent,
+            "referer": self.referer,
+            "request_id": self.request_id,
+        }).to_string()
+    }
+}
+
+// Middleware structure
+pub struct AccessLog {
+    logger: Arc<AccessLogger>,
+}
+
+impl AccessLog {
+    pub fn new(logger: Arc<AccessLogger>) -> Self {
+        AccessLog { logger }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogMiddleware {
+            service,
+            logger: Arc::clone(&self.logger),
+        })
+    }
+}
+
+// Middleware logic
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    logger: Arc<AccessLogger>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let logger = Arc::clone(&self.logger);
+
+        let header = |name: header::HeaderName| req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+
+        let mut entry = AccessLogEntry {
+            client_ip: req.extensions().get::<ClientInfo>().map(|c| c.ip).unwrap_or(req.peer_addr().map(|a| a.ip())),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map(|p| p.to_string()).unwrap_or(req.path().to_owned()),
+            route: req.match_pattern().unwrap_or(req.path().to_owned()),
+            version: format!("{:?}", req.version()),
+            status: 0,
+            bytes_in: header(header::CONTENT_LENGTH).and_then(|v| v.parse().ok()).unwrap_or(0),
+            bytes_out: 0,
+            latency_ms: 0,
+            user_id: None,
+            user_agent: header(header::USER_AGENT),
+            referer: header(header::REFERER),
+            request_id: request_id::current(),
+        };
+
+        // Chunked uploads have no Content-Length, their size is known once the handler read them
+        let bytes_in = Arc::new(AtomicU64::new(0));
+        let read = Arc::clone(&bytes_in);
+        let payload = req.take_payload().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                read.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }
+        });
+        req.set_payload(Payload::from(payload.boxed_local()));
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+
+            entry.user_id = request_id::with_context(|ctx| ctx.user_id()).flatten();
+            match res {
+                Ok(res) => {
+                    entry.status = res.status().as_u16();
+                    Ok(res.map_body(|_, body| LoggedBody { body: Box::pin(body), entry: Some(entry), start, bytes_in, logger }))
+                }
+                Err(e) => {
+                    entry.status = e.as_response_error().status_code().as_u16();
+                    entry.bytes_in = entry.bytes_in.max(bytes_in.load(Ordering::Relaxed));
+                    entry.latency_ms = start.elapsed().as_millis();
+                    logger.write(&entry);
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+/// Response body counting the bytes sent, the access log line is written once it is dropped after the last chunk
+/// or when the client went away.
+pub struct LoggedBody<B> {
+    body: Pin<Box<B>>,
+    entry: Option<AccessLogEntry>,
+    start: Instant,
+    bytes_in: Arc<AtomicU64>,
+    logger: Arc<AccessLogger>,
+}
+
+impl<B: MessageBody> MessageBody for LoggedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let chunk = this.body.as_mut().poll_next(cx);
+        if let (Poll::Ready(Some(Ok(bytes))), Some(entry)) = (&chunk, &mut this.entry) {
+            entry.bytes_out += bytes.len() as u64;
+        }
+        chunk
+    }
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.bytes_in = entry.bytes_in.max(self.bytes_in.load(Ordering::Relaxed));
+            entry.latency_ms = self.start.elapsed().as_millis();
+            self.logger.write(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use futures::stream;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn streamed_bodies_are_counted() {
+        let path = std::env::temp_dir().join(format!("access-log-{}.log", std::process::id()));
+        let file = RotatingFile::open(path.clone(), u64::MAX, 0).unwrap();
+        let logger = Arc::new(AccessLogger::new(AccessLogFormat::Json, Some(file)).unwrap());
+
+        let app = test::init_service(App::new()
+            .wrap(AccessLog::new(logger))
+            .route("/upload", web::post().to(|body: Bytes| async move {
+                let chunks = vec![Ok::<_, Error>(Bytes::from_static(b"abc")), Ok(body)];
+                HttpResponse::Ok().streaming(stream::iter(chunks))
+            }))
+        ).await;
+
+        // Chunked, without Content-Length
+        let mut req = test::TestRequest::post().uri("/upload").to_request();
+        *req.payload() = Payload::from(stream::iter(vec![Ok(Bytes::from_static(b"hel")), Ok(Bytes::from_static(b"lo"))]).boxed_local());
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert_eq!(body, "abchello");
+
+        // Written by the writer thread
+        let mut line = String::new();
+        for _ in 0..100 {
+            line = fs::read_to_string(&path).unwrap();
+            if !line.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::remove_file(&path).unwrap();
+
+        let entry: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes_in"], 5);
+        assert_eq!(entry["bytes_out"], 8);
+    }
+}
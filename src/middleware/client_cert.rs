@@ -0,0 +1,89 @@
+use crate::config::ClientPrincipalConfig;
+use crate::middleware::jwt_validator::Claims;
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use log::warn;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Identities of the verified client certificate of a TLS connection.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub subject: String,
+    /// URI, DNS and email SANs followed by the subject CN
+    pub identities: Vec<String>,
+}
+
+/// Stores the client certificate verified during the handshake in the connection data,
+/// handlers and middlewares read it with `HttpRequest::conn_data`.
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    let Some(tls) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let Some(cert) = tls.get_ref().1.peer_certificates().and_then(|certs| certs.first()) else {
+        return;
+    };
+
+    match X509Certificate::from_der(cert.as_ref()) {
+        Ok((_, cert)) => {
+            data.insert(client_certificate(&cert));
+        }
+        Err(e) => warn!("can not parse verified client certificate: {}", e),
+    }
+}
+
+fn client_certificate(cert: &X509Certificate) -> ClientCertificate {
+    let mut identities = Vec::new();
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::URI(uri) => identities.push(uri.to_string()),
+                GeneralName::DNSName(dns) => identities.push(dns.to_string()),
+                GeneralName::RFC822Name(email) => identities.push(email.to_string()),
+                _ => {}
+            }
+        }
+    }
+    identities.extend(cert.subject().iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(String::from));
+
+    ClientCertificate {
+        subject: cert.subject().to_string(),
+        identities,
+    }
+}
+
+/// Principals of partner certificates keyed by certificate identity, replaced when the config is reloaded.
+#[derive(Default)]
+pub struct ClientPrincipals {
+    principals: RwLock<HashMap<String, ClientPrincipalConfig>>,
+}
+
+impl ClientPrincipals {
+    pub fn replace(&self, principals: HashMap<String, ClientPrincipalConfig>) {
+        *self.principals.write().unwrap_or_else(|e| e.into_inner()) = principals;
+    }
+
+    /// Claims of the principal mapped from the certificate, if it may call routes of the group.
+    pub fn authorize(&self, cert: &ClientCertificate, group: &str) -> Option<Claims> {
+        let principals = self.principals.read().unwrap_or_else(|e| e.into_inner());
+
+        let principal = cert.identities.iter().find_map(|identity| principals.get(identity))?;
+        if !principal.routes.iter().any(|r| r == group) {
+            warn!("client certificate {} is not allowed to call {} routes", cert.subject, group);
+            return None;
+        }
+
+        Some(Claims {
+            sub: principal.name.clone(),
+            company: principal.company.clone(),
+            exp: 0,
+        })
+    }
+}
@@ -0,0 +1,223 @@
+use crate::config::CompressionConfig;
+use actix_http::encoding::Encoder;
+use actix_service::{Service, Transform};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, AcceptEncoding, ContentEncoding, Encoding, EntityTag, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::error;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Which responses are compressed and with what, built once from `[compression]`.
+pub struct CompressionPolicy {
+    // Always contains identity, so clients without a supported encoding get the plain body
+    encodings: Vec<Encoding>,
+    min_size: usize,
+    excluded_content_types: Vec<String>,
+}
+
+impl CompressionPolicy {
+    pub fn new(config: &CompressionConfig) -> Self {
+        let mut encodings = vec![Encoding::identity()];
+        for name in &config.encodings {
+            match name.parse::<ContentEncoding>() {
+                Ok(ContentEncoding::Brotli) => encodings.push(Encoding::brotli()),
+                Ok(ContentEncoding::Zstd) => encodings.push(Encoding::zstd()),
+                Ok(ContentEncoding::Gzip) => encodings.push(Encoding::gzip()),
+                _ => error!("ignoring unsupported compression encoding {}", name),
+            }
+        }
+
+        CompressionPolicy {
+            encodings,
+            min_size: config.min_size,
+            excluded_content_types: config.excluded_content_types.iter().map(|t| t.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    fn negotiate(&self, req: &ServiceRequest) -> ContentEncoding {
+        if self.encodings.len() == 1 {
+            return ContentEncoding::Identity;
+        }
+
+        // Clients refusing every encoding, even identity, still get the plain body
+        match req.get_header::<AcceptEncoding>().and_then(|accept| accept.negotiate(self.encodings.iter())) {
+            Some(Encoding::Known(encoding)) => encoding,
+            _ => ContentEncoding::Identity,
+        }
+    }
+
+    fn is_compressible(&self, status: StatusCode, headers: &header::HeaderMap, size: BodySize) -> bool {
+        let large_enough = match size {
+            BodySize::Sized(size) => size >= self.min_size as u64,
+            BodySize::Stream => true,
+            BodySize::None => false,
+        };
+
+        large_enough
+            && !matches!(status, StatusCode::SWITCHING_PROTOCOLS | StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED)
+            && !headers.contains_key(header::CONTENT_ENCODING)
+            && !self.is_excluded(headers.get(header::CONTENT_TYPE))
+    }
+
+    fn is_excluded(&self, content_type: Option<&HeaderValue>) -> bool {
+        // Parameters like `charset` do not matter
+        let Some(essence) = content_type.and_then(|v| v.to_str().ok()).and_then(|v| v.split(';').next()) else {
+            return false;
+        };
+        let essence = essence.trim().to_ascii_lowercase();
+
+        self.excluded_content_types.iter().any(|excluded| match excluded.strip_suffix('*') {
+            Some(prefix) => essence.starts_with(prefix),
+            None => essence == *excluded,
+        })
+    }
+}
+
+// Encodings whose name is appended to strong ETags of encoded bodies, e.g. `"3-gzip"`
+const ETAG_CODINGS: [ContentEncoding; 3] = [ContentEncoding::Brotli, ContentEncoding::Zstd, ContentEncoding::Gzip];
+
+/// Tag of the plain body a strong ETag of an encoded body was derived from, e.g. `"3"` for `"3-gzip"`.
+pub fn without_coding(tag: &EntityTag) -> Option<EntityTag> {
+    if tag.weak {
+        return None;
+    }
+    ETAG_CODINGS.iter()
+        .find_map(|coding| tag.tag().strip_suffix(coding.as_str())?.strip_suffix('-'))
+        .map(|plain| EntityTag::new_strong(plain.to_owned()))
+}
+
+/// Matches a precondition tag against the ETag of the plain body, tags of encoded bodies only match the strong
+/// ETag they were derived from. Returns the tag of the representation the client has.
+pub fn match_etag(tag: &EntityTag, etag: &EntityTag) -> Option<EntityTag> {
+    if tag.weak_eq(etag) {
+        return Some(etag.clone());
+    }
+    without_coding(tag).filter(|plain| plain.strong_eq(etag)).map(|_| tag.clone())
+}
+
+// Different encodings of a body are different representations, they can not share a strong ETag
+fn tag_etag(headers: &mut header::HeaderMap, encoding: ContentEncoding) {
+    let Some(etag) = headers.get(header::ETAG).and_then(|v| v.to_str().ok()?.parse::<EntityTag>().ok()) else {
+        return;
+    };
+    if etag.weak {
+        return;
+    }
+    let tagged = EntityTag::new_strong(format!("{}-{}", etag.tag(), encoding.as_str()));
+    if let Ok(value) = HeaderValue::from_str(&tagged.to_string()) {
+        headers.insert(header::ETAG, value);
+    }
+}
+
+// Middleware structure
+pub struct Compression {
+    policy: Arc<CompressionPolicy>,
+}
+
+impl Compression {
+    pub fn new(policy: Arc<CompressionPolicy>) -> Self {
+        Compression { policy }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for Compression
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<Encoder<B>>;
+    type Error = Error;
+    type Transform = CompressionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CompressionMiddleware {
+            service,
+            policy: Arc::clone(&self.policy),
+        })
+    }
+}
+
+// Middleware logic
+pub struct CompressionMiddleware<S> {
+    service: S,
+    policy: Arc<CompressionPolicy>,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<Encoder<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let encoding = self.policy.negotiate(&req);
+        let policy = Arc::clone(&self.policy);
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+
+            Ok(res.map_body(|head, body| {
+                if !policy.is_compressible(head.status, &head.headers, body.size()) {
+                    return Encoder::response(ContentEncoding::Identity, head, body);
+                }
+
+                if encoding != ContentEncoding::Identity {
+                    tag_etag(&mut head.headers, encoding);
+                }
+
+                // The encoder adds `Vary` itself when it compresses
+                if encoding == ContentEncoding::Identity {
+                    head.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+                }
+                Encoder::response(encoding, head, body)
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_tags_match_the_tag_they_were_derived_from() {
+        let etag = EntityTag::new_strong("3".to_owned());
+
+        assert_eq!(match_etag(&EntityTag::new_strong("3-gzip".to_owned()), &etag), Some(EntityTag::new_strong("3-gzip".to_owned())));
+        assert_eq!(match_etag(&EntityTag::new_strong("3".to_owned()), &etag), Some(etag.clone()));
+        assert_eq!(match_etag(&EntityTag::new_strong("4-gzip".to_owned()), &etag), None);
+    }
+
+    #[test]
+    fn tags_ending_like_a_coding_are_compared_as_they_are() {
+        let etag = EntityTag::new_strong("v-gzip".to_owned());
+
+        assert_eq!(match_etag(&EntityTag::new_strong("v-gzip".to_owned()), &etag), Some(etag.clone()));
+        assert_eq!(match_etag(&EntityTag::new_strong("v".to_owned()), &etag), None);
+        assert_eq!(match_etag(&EntityTag::new_strong("v-gzip-br".to_owned()), &etag), Some(EntityTag::new_strong("v-gzip-br".to_owned())));
+    }
+
+    #[test]
+    fn weak_tags_have_no_coding() {
+        assert_eq!(without_coding(&EntityTag::new_weak("3-gzip".to_owned())), None);
+        assert_eq!(without_coding(&EntityTag::new_strong("3-zstd".to_owned())), Some(EntityTag::new_strong("3".to_owned())));
+        assert_eq!(without_coding(&EntityTag::new_strong("3gzip".to_owned())), None);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::CacheHeadersConfig;
+use crate::middleware::compression::match_etag;
+use crate::middleware::jwt_validator::Claims;
+use crate::middleware::stored_response::StoredResponse;
+use actix_service::{Service, Transform};
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, EntityTag, Header, HeaderValue, HttpDate, IfModifiedSince, IfNoneMatch, TryIntoHeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::error;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Revalidate on every use, the responses depend on the caller
+const DEFAULT_CACHE_CONTROL: &str = "private, no-cache";
+
+// Number of URLs per tenant whose last change is remembered for `Last-Modified`
+const TRACKED_RESOURCES: usize = 10_000;
+
+#[derive(Clone)]
+struct RouteHeaders {
+    cache_control: HeaderValue,
+    last_modified: bool,
+}
+
+/// Cache validators of GET responses, `Cache-Control` and `Last-Modified` are configured per route pattern.
+pub struct CacheHeaders {
+    routes: RwLock<HashMap<String, RouteHeaders>>,
+    // ETag of a tenant's URL and the time it was first served, which becomes its `Last-Modified`
+    versions: Mutex<LruCache<(String, String), (EntityTag, SystemTime)>>,
+}
+
+impl CacheHeaders {
+    pub fn new(config: &HashMap<String, CacheHeadersConfig>) -> Self {
+        let headers = CacheHeaders {
+            routes: RwLock::new(HashMap::new()),
+            versions: Mutex::new(LruCache::new(NonZeroUsize::new(TRACKED_RESOURCES).unwrap_or(NonZeroUsize::MIN))),
+        };
+        headers.replace(config);
+        headers
+    }
+
+    pub fn replace(&self, config: &HashMap<String, CacheHeadersConfig>) {
+        let routes = config.iter()
+            .filter_map(|(route, headers)| {
+                let cache_control = headers.cache_control.as_deref().unwrap_or(DEFAULT_CACHE_CONTROL);
+                match HeaderValue::from_str(cache_control) {
+                    Ok(cache_control) => Some((route.clone(), RouteHeaders { cache_control, last_modified: headers.last_modified })),
+                    Err(_) => {
+                        error!("ignoring invalid Cache-Control of route {}", route);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        *self.routes.write().unwrap_or_else(|e| e.into_inner()) = routes;
+    }
+
+    fn route(&self, route: &str) -> RouteHeaders {
+        let routes = self.routes.read().unwrap_or_else(|e| e.into_inner());
+        routes.get(route).cloned().unwrap_or_else(|| RouteHeaders {
+            cache_control: HeaderValue::from_static(DEFAULT_CACHE_CONTROL),
+            last_modified: false,
+        })
+    }
+
+    // Time the current representation was first served, a new ETag counts as a modification
+    fn last_modified(&self, key: (String, String), etag: &EntityTag) -> SystemTime {
+        let mut versions = self.versions.lock().unwrap_or_else(|e| e.into_inner());
+        match versions.get(&key) {
+            Some((known, modified)) if known.strong_eq(etag) => *modified,
+            _ => {
+                // HTTP dates have a resolution of seconds
+                let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let now = UNIX_EPOCH + Duration::from_secs(secs);
+                versions.put(key, (etag.clone(), now));
+                now
+            }
+        }
+    }
+}
+
+fn strong_etag(body: &[u8]) -> EntityTag {
+    let digest = Sha256::digest(body);
+    let hex = digest[..16].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    EntityTag::new_strong(hex)
+}
+
+// If-None-Match takes precedence over If-Modified-Since, RFC 9110 section 13.2.2,
+// returns the ETag of the representation the client has
+fn not_modified_etag(req: &HttpRequest, etag: &EntityTag, last_modified: Option<SystemTime>) -> Option<EntityTag> {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => Some(etag.clone()),
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().find_map(|tag| match_etag(tag, etag)),
+            Err(_) => None,
+        };
+    }
+
+    match (last_modified, IfModifiedSince::parse(req)) {
+        (Some(last_modified), Ok(IfModifiedSince(since))) if last_modified <= SystemTime::from(since) => Some(etag.clone()),
+        _ => None,
+    }
+}
+
+// Middleware structure
+pub struct ConditionalGet {
+    headers: Arc<CacheHeaders>,
+}
+
+impl ConditionalGet {
+    pub fn new(headers: Arc<CacheHeaders>) -> Self {
+        ConditionalGet { headers }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for ConditionalGet
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = ConditionalGetMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConditionalGetMiddleware {
+            service,
+            headers: Arc::clone(&self.headers),
+        })
+    }
+}
+
+// Middleware logic
+pub struct ConditionalGetMiddleware<S> {
+    service: S,
+    headers: Arc<CacheHeaders>,
+}
+
+impl<S, B> Service<ServiceRequest> for ConditionalGetMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if req.method() != Method::GET {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        }
+
+        let route = self.headers.route(&req.match_pattern().unwrap_or_default());
+        let resource = (
+            req.extensions().get::<Claims>().map(|c| c.company.clone()).unwrap_or_default(),
+            req.uri().path_and_query().map(|p| p.to_string()).unwrap_or_default(),
+        );
+        let headers = Arc::clone(&self.headers);
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            if res.status() != StatusCode::OK {
+                return Ok(res.map_into_left_body());
+            }
+
+            // Handlers may set their own validator, e.g. the version of a product
+            let (mut res, etag) = match res.headers().get(header::ETAG).and_then(|v| v.to_str().ok()?.parse::<EntityTag>().ok()) {
+                Some(etag) => (res.map_into_boxed_body(), etag),
+                None => {
+                    let (mut res, stored) = StoredResponse::capture(res).await?;
+                    let etag = strong_etag(&stored.body);
+                    res.headers_mut().insert(header::ETAG, HeaderValue::from_str(&etag.to_string())?);
+                    (res, etag)
+                }
+            };
+
+            let last_modified = route.last_modified.then(|| headers.last_modified(resource, &etag));
+
+            if let Some(etag) = not_modified_etag(res.request(), &etag, last_modified) {
+                let mut not_modified = HttpResponse::NotModified();
+                not_modified.insert_header(header::ETag(etag));
+                not_modified.insert_header((header::CACHE_CONTROL, route.cache_control));
+                if let Some(last_modified) = last_modified {
+                    not_modified.insert_header(header::LastModified(last_modified.into()));
+                }
+                let (req, _) = res.into_parts();
+                return Ok(ServiceResponse::new(req, not_modified.finish()).map_into_right_body());
+            }
+
+            res.headers_mut().insert(header::CACHE_CONTROL, route.cache_control);
+            if let Some(last_modified) = last_modified {
+                res.headers_mut().insert(header::LAST_MODIFIED, HttpDate::from(last_modified).try_into_value()?);
+            }
+
+            Ok(res.map_into_right_body())
+        })
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config::CorsGroupConfig;
+use crate::error::Error;
+use crate::middleware::idempotency::{IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER};
+use crate::middleware::request_id::REQUEST_ID_HEADER;
+use crate::routes::NEXT_CURSOR_HEADER;
+use actix_cors::Cors;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const DEFAULT_ALLOWED_HEADERS: [&str; 8] = ["authorization", "content-type", "if-match", IDEMPOTENCY_KEY_HEADER, REQUEST_ID_HEADER, "x-grpc-web", "x-user-agent", "grpc-timeout"];
+
+const DEFAULT_MAX_AGE: usize = 3600;
+
+// Exposed on every group, clients need them for tracing, paging through lists, conditional updates and retries
+const ALWAYS_EXPOSED_HEADERS: [&str; 7] = [REQUEST_ID_HEADER, "link", NEXT_CURSOR_HEADER, "etag", REPLAYED_HEADER, "grpc-status", "grpc-message"];
+
+/// Allowed origin, `https://*.example.com` matches any subdomain of `example.com` but not `example.com` itself.
+#[derive(Debug, Clone)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    Subdomain { scheme: String, suffix: String },
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_end_matches('/').to_ascii_lowercase();
+        if pattern.is_empty() {
+            return None;
+        }
+        if pattern == "*" {
+            return Some(OriginPattern::Any);
+        }
+
+        let (scheme, host) = pattern.split_once("://")?;
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() => Some(OriginPattern::Subdomain {
+                scheme: scheme.to_owned(),
+                suffix: format!(".{}", domain),
+            }),
+            Some(_) => None,
+            None => Some(OriginPattern::Exact(pattern)),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(exact) => *exact == origin,
+            OriginPattern::Subdomain { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|rest| rest.strip_prefix("://"))
+                .map(|host| {
+                    // Ignore the port, only the host has to be a subdomain
+                    let host = host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host);
+                    host.len() > suffix.len() && host.ends_with(suffix.as_str())
+                })
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// CORS policies of the route groups, built per resource in `routes::init_routes`.
+pub struct CorsPolicies {
+    origins: Arc<Vec<OriginPattern>>,
+    groups: HashMap<String, CorsGroupConfig>,
+    group_origins: HashMap<String, Arc<Vec<OriginPattern>>>,
+}
+
+impl CorsPolicies {
+    /// Fails for invalid origins of a group and for credentials allowed to any origin.
+    pub fn new(origins: Vec<OriginPattern>, groups: HashMap<String, CorsGroupConfig>) -> Result<Self, Error> {
+        let origins = Arc::new(origins);
+
+        let mut group_origins = HashMap::new();
+        for (group, config) in &groups {
+            let patterns = match &config.origins {
+                Some(patterns) => Arc::new(patterns.iter()
+                    .map(|o| OriginPattern::parse(o).ok_or_else(|| Error::InvalidConfig(format!("cors.{}: invalid origin {}", group, o))))
+                    .collect::<Result<Vec<_>, _>>()?),
+                None => Arc::clone(&origins),
+            };
+            // Credentials would be sent along with requests of any site
+            if config.supports_credentials && patterns.iter().any(|p| matches!(p, OriginPattern::Any)) {
+                return Err(Error::InvalidConfig(format!("cors.{}: supports_credentials can not be combined with the origin *", group)));
+            }
+            group_origins.insert(group.clone(), patterns);
+        }
+
+        Ok(CorsPolicies { origins, groups, group_origins })
+    }
+
+    /// Builds the CORS middleware of a route group, `default_methods` are used unless the config lists them.
+    pub fn build(&self, group: &str, default_methods: &[&str]) -> Cors {
+        let config = self.groups.get(group).cloned().unwrap_or_default();
+        let origins = Arc::clone(self.group_origins.get(group).unwrap_or(&self.origins));
+
+        let mut cors = Cors::default()
+            .allowed_origin_fn(move |origin, _| {
+                origin.to_str().map(|o| origins.iter().any(|p| p.matches(o))).unwrap_or(false)
+            })
+            .max_age(config.max_age.unwrap_or(DEFAULT_MAX_AGE));
+
+        cors = match &config.methods {
+            Some(methods) => cors.allowed_methods(methods.iter().map(String::as_str)),
+            None => cors.allowed_methods(default_methods.iter().copied()),
+        };
+
+        cors = match &config.headers {
+            Some(headers) => cors.allowed_headers(headers.iter().map(String::as_str)),
+            None => cors.allowed_headers(DEFAULT_ALLOWED_HEADERS),
+        };
+
+        let mut expose_headers = config.expose_headers.clone().unwrap_or_default();
+        for header in ALWAYS_EXPOSED_HEADERS {
+            if !expose_headers.iter().any(|h| h.eq_ignore_ascii_case(header)) {
+                expose_headers.push(header.to_owned());
+            }
+        }
+        cors = cors.expose_headers(expose_headers.iter().map(String::as_str));
+
+        if config.supports_credentials {
+            cors = cors.supports_credentials();
+        }
+
+        cors
+    }
+}
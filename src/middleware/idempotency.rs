@@ -0,0 +1,259 @@
+use crate::error::api_error;
+use crate::middleware::jwt_validator::Claims;
+use crate::middleware::stored_response::StoredResponse;
+use actix_service::{Service, Transform};
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, ready, LocalBoxFuture, Ready};
+use futures::{stream, StreamExt};
+use log::{info, warn};
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses replayed from the store instead of reaching the backend
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+// Keys are scoped to the user, so different users never see each other's responses
+type EntryId = (String, String);
+
+enum State {
+    InFlight,
+    Completed(StoredResponse),
+}
+
+struct Entry {
+    // Hash of method, path and body of the first request
+    fingerprint: u64,
+    state: State,
+    expires: Instant,
+}
+
+enum Begin {
+    Started,
+    Replay(StoredResponse),
+    InFlight,
+    Mismatch,
+}
+
+/// First responses of requests with an `Idempotency-Key`, kept in memory for `ttl`,
+/// the least recently used key is dropped once `max_keys` are stored.
+pub struct IdempotencyStore {
+    entries: Mutex<LruCache<EntryId, Entry>>,
+    ttl: Duration,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration, max_keys: NonZeroUsize) -> Self {
+        IdempotencyStore { entries: Mutex::new(LruCache::new(max_keys)), ttl }
+    }
+
+    fn begin(&self, id: &EntryId, fingerprint: u64) -> Begin {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        if let Some(entry) = entries.get(id).filter(|e| e.expires > now) {
+            if entry.fingerprint != fingerprint {
+                return Begin::Mismatch;
+            }
+            return match &entry.state {
+                State::InFlight => Begin::InFlight,
+                State::Completed(response) => Begin::Replay(response.clone()),
+            };
+        }
+
+        while entries.peek_lru().is_some_and(|(_, e)| e.expires <= now) {
+            entries.pop_lru();
+        }
+        entries.put(id.clone(), Entry { fingerprint, state: State::InFlight, expires: now + self.ttl });
+        Begin::Started
+    }
+
+    fn complete(&self, id: &EntryId, response: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.peek_mut(id) {
+            entry.state = State::Completed(response);
+        }
+    }
+
+    // Lets the client retry after a failure which did not produce a final response
+    fn abort(&self, id: &EntryId) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.peek(id).is_some_and(|e| matches!(e.state, State::InFlight)) {
+            entries.pop(id);
+        }
+    }
+}
+
+// Aborts the entry when the request is dropped before completing, e.g. the client disconnected
+struct InFlightGuard {
+    store: Arc<IdempotencyStore>,
+    id: EntryId,
+    completed: bool,
+}
+
+impl InFlightGuard {
+    fn complete(mut self, response: StoredResponse) {
+        self.store.complete(&self.id, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.abort(&self.id);
+        }
+    }
+}
+
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    req.method().as_str().hash(&mut hasher);
+    req.path().hash(&mut hasher);
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+async fn read_body(payload: &mut Payload, limit: usize) -> Result<Option<Bytes>, Error> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body.freeze()))
+}
+
+// Middleware structure
+pub struct Idempotency {
+    store: Arc<IdempotencyStore>,
+    // Largest body that is buffered to be fingerprinted, same as the JSON limit of the route
+    body_limit: usize,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<IdempotencyStore>, body_limit: usize) -> Self {
+        Idempotency { store, body_limit }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            store: Arc::clone(&self.store),
+            body_limit: self.body_limit,
+        })
+    }
+}
+
+// Middleware logic
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<IdempotencyStore>,
+    body_limit: usize,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned() else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        };
+
+        let key = match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_owned(),
+            _ => {
+                let res = req.error_response(api_error(StatusCode::BAD_REQUEST, format!("Idempotency-Key must be 1 to {} visible ASCII characters", MAX_KEY_LENGTH)));
+                return Box::pin(async { Ok(res.map_into_right_body()) });
+            }
+        };
+        let user = req.extensions().get::<Claims>().map(|c| c.sub.clone()).unwrap_or_default();
+        let id = (user, key);
+
+        let service = Rc::clone(&self.service);
+        let store = Arc::clone(&self.store);
+        let body_limit = self.body_limit;
+
+        Box::pin(async move {
+            let Some(body) = read_body(&mut req.take_payload(), body_limit).await? else {
+                return Ok(req.error_response(api_error(StatusCode::PAYLOAD_TOO_LARGE, format!("payload is larger than allowed (limit: {} bytes)", body_limit))).map_into_right_body());
+            };
+            let fingerprint = fingerprint(&req, &body);
+            req.set_payload(Payload::from(stream::once(ready(Ok::<_, PayloadError>(body))).boxed_local()));
+
+            match store.begin(&id, fingerprint) {
+                Begin::Started => {}
+                Begin::Replay(stored) => {
+                    info!("replaying response of idempotency key {} for {} {}", id.1, req.method(), req.path());
+                    let mut res = stored.to_response();
+                    res.headers_mut().insert(HeaderName::from_static(REPLAYED_HEADER), HeaderValue::from_static("true"));
+                    return Ok(req.into_response(res).map_into_right_body());
+                }
+                Begin::InFlight => {
+                    warn!("idempotency key {} is still in flight", id.1);
+                    return Ok(req.error_response(api_error(StatusCode::CONFLICT, "A request with this Idempotency-Key is still in progress")).map_into_right_body());
+                }
+                Begin::Mismatch => {
+                    warn!("idempotency key {} reused with a different request", id.1);
+                    return Ok(req.error_response(api_error(StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key was already used with a different request")).map_into_right_body());
+                }
+            }
+
+            let guard = InFlightGuard { store, id, completed: false };
+            let res = service.call(req).await?;
+
+            // Server errors are not stored, the client may retry them with the same key
+            if res.status().is_server_error() {
+                return Ok(res.map_into_left_body());
+            }
+
+            let (res, stored) = StoredResponse::capture(res).await?;
+            guard.complete(stored);
+
+            Ok(res.map_into_right_body())
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use crate::config::IpFilterConfig;
+use crate::error::api_error;
+use crate::middleware::metrics::MetricsPublisher;
+use crate::middleware::trusted_proxies::ClientInfo;
+use actix_service::{Service, Transform};
+use actix_web::body::EitherBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::warn;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+/// Allow and deny lists of all route groups, replaced as a whole when the config is reloaded.
+#[derive(Default)]
+pub struct IpFilters {
+    groups: RwLock<HashMap<String, IpFilterConfig>>,
+}
+
+impl IpFilters {
+    pub fn replace(&self, groups: HashMap<String, IpFilterConfig>) {
+        *self.groups.write().unwrap_or_else(|e| e.into_inner()) = groups;
+    }
+
+    // Deny rules win over allow rules, an empty allow list allows everyone not denied
+    fn is_allowed(&self, group: &str, ip: Option<IpAddr>) -> bool {
+        let groups = self.groups.read().unwrap_or_else(|e| e.into_inner());
+        let Some(rules) = groups.get(group) else {
+            return true;
+        };
+        let Some(ip) = ip.map(|ip| ip.to_canonical()) else {
+            return rules.allow.is_empty() && rules.deny.is_empty();
+        };
+
+        if rules.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        rules.allow.is_empty() || rules.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+// Middleware structure
+pub struct IpFilter {
+    filters: Arc<IpFilters>,
+    group: &'static str,
+    publisher: Arc<MetricsPublisher>,
+    methods: Option<&'static [&'static str]>,
+}
+
+impl IpFilter {
+    pub fn new(filters: Arc<IpFilters>, group: &'static str, publisher: Arc<MetricsPublisher>) -> Self {
+        IpFilter { filters, group, publisher, methods: None }
+    }
+
+    /// Only filters requests with one of the methods, so the filter can wrap a whole resource outside its `JwtValidator`.
+    pub fn methods(mut self, methods: &'static [&'static str]) -> Self {
+        self.methods = Some(methods);
+        self
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for IpFilter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = IpFilterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IpFilterMiddleware {
+            service,
+            filters: Arc::clone(&self.filters),
+            group: self.group,
+            publisher: Arc::clone(&self.publisher),
+            methods: self.methods,
+        })
+    }
+}
+
+// Middleware logic
+pub struct IpFilterMiddleware<S> {
+    service: S,
+    filters: Arc<IpFilters>,
+    group: &'static str,
+    publisher: Arc<MetricsPublisher>,
+    methods: Option<&'static [&'static str]>,
+}
+
+impl<S, B> Service<ServiceRequest> for IpFilterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let ip = req.extensions().get::<ClientInfo>()
+            .map(|c| c.ip)
+            .unwrap_or(req.peer_addr().map(|a| a.ip()));
+
+        let filtered = match self.methods {
+            Some(methods) => methods.contains(&req.method().as_str()),
+            None => true,
+        };
+        if !filtered || self.filters.is_allowed(self.group, ip) {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        }
+
+        warn!("blocked {} {} from {} by ip filter {}", req.method(), req.path(), ip.map(|ip| ip.to_string()).unwrap_or("unknown".to_owned()), self.group);
+        self.publisher.spawn_publish(format!("blocked_requests,method={},request_path={},group={} count=1", req.method(), req.path(), self.group));
+
+        let res = req.error_response(api_error(StatusCode::FORBIDDEN, "Forbidden"));
+        Box::pin(async { Ok(res.map_into_right_body()) })
+    }
+}
@@ -1,7 +1,9 @@
 use std::sync::Arc;
-use crate::error::Error as AppError;
+use crate::error::api_error;
+use crate::middleware::client_cert::{ClientCertificate, ClientPrincipals};
+use crate::middleware::request_id;
 use actix_service::{Service, Transform};
-use actix_web::{dev::{ServiceRequest, ServiceResponse}, Error};
+use actix_web::{body::EitherBody, dev::{ServiceRequest, ServiceResponse}, http::StatusCode, Error, HttpMessage};
 use futures::future::{ok, LocalBoxFuture, Ready};
 use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
 use serde::{Deserialize, Serialize};
@@ -11,11 +13,14 @@ use std::task::{Context, Poll};
 // Middleware structure
 pub struct JwtValidator {
     pub secret: Arc<String>,
+    pub principals: Arc<ClientPrincipals>,
+    // Route group partners with a client certificate have to be allowed to call
+    pub group: &'static str,
 }
 
 impl JwtValidator {
-    pub fn new(secret: Arc<String>) -> Self {
-        JwtValidator { secret }
+    pub fn new(secret: Arc<String>, principals: Arc<ClientPrincipals>, group: &'static str) -> Self {
+        JwtValidator { secret, principals, group }
     }
 }
 
@@ -26,7 +31,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Transform = JwtValidatorMiddleware<S>;
     type InitError = ();
@@ -36,6 +41,8 @@ where
         ok(JwtValidatorMiddleware {
             service,
             secret: Arc::clone(&self.secret),
+            principals: Arc::clone(&self.principals),
+            group: self.group,
         })
     }
 }
@@ -44,6 +51,8 @@ where
 pub struct JwtValidatorMiddleware<S> {
     service: S,
     secret: Arc<String>,
+    principals: Arc<ClientPrincipals>,
+    group: &'static str,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtValidatorMiddleware<S>
@@ -52,7 +61,7 @@ where
     S::Future: 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
     // type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
@@ -62,6 +71,17 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Partners authenticate with a verified client certificate instead of a token
+        let cert_claims = req.conn_data::<ClientCertificate>()
+            .and_then(|cert| self.principals.authorize(cert, self.group));
+        if let Some(claims) = cert_claims {
+            request_id::set_user_id(&claims.sub);
+            request_id::set_tenant(&claims.company);
+            req.extensions_mut().insert(claims);
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        }
+
         // Extract the token from the Authorization header
         let token = req
             .headers()
@@ -72,30 +92,36 @@ where
 
         if let Some(token) = token {
             match validate_jwt(&token, self.secret.as_str()) {
-                Ok(_) => {
-                    Box::pin(self.service.call(req))
+                Ok(token_data) => {
+                    request_id::set_user_id(&token_data.claims.sub);
+                    request_id::set_tenant(&token_data.claims.company);
+                    req.extensions_mut().insert(token_data.claims);
+                    let fut = self.service.call(req);
+                    Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
                 }
                 Err(_) => {
                     // Invalid token, return Unauthorized response
-                    Box::pin(async { Err(actix_web::error::ErrorUnauthorized("Unauthorized")) })
+                    let res = req.error_response(api_error(StatusCode::UNAUTHORIZED, "Unauthorized"));
+                    Box::pin(async { Ok(res.map_into_right_body()) })
                 }
             }
         } else {
             // No token, return Unauthorized response
-            Box::pin(async { Err(actix_web::error::ErrorUnauthorized("Unauthorized")) })
+            let res = req.error_response(api_error(StatusCode::UNAUTHORIZED, "Unauthorized"));
+            Box::pin(async { Ok(res.map_into_right_body()) })
         }
     }
 }
 
-#[warn(private_interfaces)]
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    company: String,
-    exp: usize, // Expiration time (as UTC timestamp)
+// Claims of a validated token, stored in the request extensions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub company: String,
+    pub exp: usize, // Expiration time (as UTC timestamp)
 }
 
-fn validate_jwt(token: &str, secret: &str) -> Result<TokenData<Claims>, AppError> {
+fn validate_jwt(token: &str, secret: &str) -> jsonwebtoken::errors::Result<TokenData<Claims>> {
     let token_data = decode::<Claims>(
         token,
         &DecodingKey::from_secret(secret.as_ref()),
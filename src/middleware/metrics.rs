@@ -8,7 +8,8 @@ use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Instant;
 
-pub struct MetricsMiddleware {
+/// Writes metrics in line protocol to InfluxDB.
+pub struct MetricsPublisher {
     pub influxdb_client: Arc<Client>,
     pub token: Arc<String>,
     pub url: Arc<String>,
@@ -16,9 +17,9 @@ pub struct MetricsMiddleware {
     pub bucket: Arc<String>,
 }
 
-impl MetricsMiddleware {
+impl MetricsPublisher {
     pub fn new(influxdb_client: Arc<Client>, token: Arc<String>, url: Arc<String>, org: Arc<String>, bucket: Arc<String>) -> Self {
-        MetricsMiddleware {
+        MetricsPublisher {
             influxdb_client,
             token,
             url,
@@ -26,6 +27,50 @@ impl MetricsMiddleware {
             bucket,
         }
     }
+
+    /// Publishes the data point in the background without waiting for InfluxDB.
+    pub fn spawn_publish(self: &Arc<Self>, data: String) {
+        let publisher = Arc::clone(self);
+        tokio::spawn(async move {
+            publisher.publish(data).await;
+        });
+    }
+
+    pub async fn publish(&self, data: String) {
+        let write_url = format!("{}/api/v2/write?org={}&bucket={}&precision=ms", self.url, self.org, self.bucket);
+
+        // Send the request
+        let response = self.influxdb_client
+            .post(&write_url)
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "text/plain")
+            .body(data)
+            .send()
+            .await;
+
+        match response {
+            Ok(res) => {
+                if res.status().is_success() {
+                    info!("Data point sent successfully!");
+                } else {
+                    error!("Failed to send data point. Status: {}", res.status());
+                }
+            }
+            Err(e) => {
+                error!("Failed to send data point. Error: {}", e);
+            }
+        }
+    }
+}
+
+pub struct MetricsMiddleware {
+    pub publisher: Arc<MetricsPublisher>,
+}
+
+impl MetricsMiddleware {
+    pub fn new(publisher: Arc<MetricsPublisher>) -> Self {
+        MetricsMiddleware { publisher }
+    }
 }
 
 // Implement `Transform` for middleware
@@ -44,11 +89,7 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(MetricsMiddlewareService {
             service,
-            influxdb_client: Arc::clone(&self.influxdb_client),
-            token: Arc::clone(&self.token),
-            url: Arc::clone(&self.url),
-            org: Arc::clone(&self.org),
-            bucket: Arc::clone(&self.bucket)
+            publisher: Arc::clone(&self.publisher),
         })
     }
 }
@@ -56,11 +97,7 @@ where
 // Middleware logic
 pub struct MetricsMiddlewareService<S> {
     service: S,
-    influxdb_client: Arc<Client>,
-    token: Arc<String>,
-    url: Arc<String>,
-    org: Arc<String>,
-    bucket: Arc<String>,
+    publisher: Arc<MetricsPublisher>,
 }
 
 impl<S, B> Service<ServiceRequest> for MetricsMiddlewareService<S>
@@ -81,11 +118,7 @@ where
         let method = req.method().to_string();
         let path = req.path().to_string();
         let start = Instant::now();
-        let influxdb_client = Arc::clone(&self.influxdb_client);
-        let token = Arc::clone(&self.token);
-        let org = Arc::clone(&self.org);
-        let bucket = Arc::clone(&self.bucket);
-        let url = Arc::clone(&self.url);
+        let publisher = Arc::clone(&self.publisher);
 
         let fut = self.service.call(req);
 
@@ -100,41 +133,9 @@ where
             }
 
             // Publish metrics to InfluxDB asynchronously (without waiting)
-            tokio::spawn(async move {
-                publish_metric(influxdb_client, metric_name, &method, &path, token.as_str(), url.as_str(), org.as_str(), bucket.as_str(), status_code.as_u16(), duration).await;
-            });
+            publisher.spawn_publish(format!("{},method={},request_path={},status={} response_time={}", metric_name, method, path, status_code.as_u16(), duration));
 
             Ok(res)
         })
     }
 }
-
-pub async fn publish_metric(client: Arc<Client>, metric_name: &str, method: &str, path: &str, token: &str, url: &str, org: &str, bucket: &str, status_code: u16, duration: u128) {
-
-    let data = format!("{},method={},request_path={},status={} response_time={}", metric_name, method, path, status_code, duration);
-    // let data = format!("requests,name={} count=1", method);
-
-    let write_url = format!("{}/api/v2/write?org={}&bucket={}&precision=ms", url, org, bucket);
-
-    // Send the request
-    let response = client
-        .post(&write_url)
-        .header("Authorization", format!("Token {}", token))
-        .header("Content-Type", "text/plain")
-        .body(data)
-        .send()
-        .await;
-
-    match response {
-        Ok(res) => {
-            if res.status().is_success() {
-                info!("Data point sent successfully!");
-            } else {
-                error!("Failed to send data point. Status: {}", res.status());
-            }
-        }
-        Err(e) => {
-            error!("Failed to send data point. Error: {}", e);
-        }
-    }
-}
\ No newline at end of file
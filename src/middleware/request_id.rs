@@ -0,0 +1,181 @@
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpResponse, ResponseError};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::metadata::MetadataValue;
+use tonic::Status;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Per request data attached to every log record written while the request is handled.
+pub struct RequestContext {
+    pub request_id: String,
+    pub route: String,
+    pub started: Instant,
+    user_id: RefCell<Option<String>>,
+    tenant: RefCell<Option<String>>,
+}
+
+impl RequestContext {
+    pub fn user_id(&self) -> Option<String> {
+        self.user_id.borrow().clone()
+    }
+
+    /// Company of the authenticated user.
+    pub fn tenant(&self) -> Option<String> {
+        self.tenant.borrow().clone()
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: Rc<RequestContext>;
+}
+
+/// Id of the request currently being handled, if any.
+pub fn current() -> Option<String> {
+    with_context(|ctx| ctx.request_id.clone())
+}
+
+pub fn with_context<R>(f: impl FnOnce(&RequestContext) -> R) -> Option<R> {
+    REQUEST_CONTEXT.try_with(|ctx| f(ctx)).ok()
+}
+
+/// Records the authenticated user of the current request.
+pub fn set_user_id(user_id: &str) {
+    with_context(|ctx| *ctx.user_id.borrow_mut() = Some(user_id.to_owned()));
+}
+
+/// Records the company of the authenticated user of the current request.
+pub fn set_tenant(tenant: &str) {
+    with_context(|ctx| *ctx.tenant.borrow_mut() = Some(tenant.to_owned()));
+}
+
+/// gRPC interceptor forwarding the current request id as metadata to the backend services.
+pub fn propagate(mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+    if let Some(id) = current() {
+        if let Ok(value) = MetadataValue::try_from(id.as_str()) {
+            request.metadata_mut().insert(REQUEST_ID_HEADER, value);
+        }
+    }
+    Ok(request)
+}
+
+// Middleware structure
+pub struct RequestIdMiddleware;
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddlewareService { service })
+    }
+}
+
+// Middleware logic
+pub struct RequestIdMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Reuse the caller's id when it is sane, otherwise generate a new one
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(String::from)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let context = Rc::new(RequestContext {
+            request_id: request_id.clone(),
+            route: req.match_pattern().unwrap_or_else(|| req.path().to_owned()),
+            started: Instant::now(),
+            user_id: RefCell::new(None),
+            tenant: RefCell::new(None),
+        });
+
+        // Inner services may log both while building their future and while it is polled
+        let fut = REQUEST_CONTEXT.sync_scope(Rc::clone(&context), || self.service.call(req));
+        let fut = REQUEST_CONTEXT.scope(context, fut);
+
+        Box::pin(async move {
+            // Errors of the inner services only become responses after this middleware, they carry the header themselves
+            let mut res = fut.await.map_err(|error| WithRequestId { error, request_id: request_id.clone() })?;
+
+            insert_request_id(res.headers_mut(), &request_id);
+
+            Ok(res)
+        })
+    }
+}
+
+// Inner error whose response carries the request id
+#[derive(Debug)]
+struct WithRequestId {
+    error: Error,
+    request_id: String,
+}
+
+impl fmt::Display for WithRequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl ResponseError for WithRequestId {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = self.error.error_response();
+        insert_request_id(res.headers_mut(), &self.request_id);
+        res
+    }
+}
+
+fn insert_request_id(headers: &mut actix_web::http::header::HeaderMap, request_id: &str) {
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
@@ -0,0 +1,208 @@
+use crate::config::ResponseCacheConfig;
+use crate::middleware::jwt_validator::Claims;
+use crate::middleware::metrics::MetricsPublisher;
+use crate::middleware::stored_response::StoredResponse;
+use actix_service::{Service, Transform};
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::debug;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const CACHE_STATUS_HEADER: &str = "x-cache";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    group: &'static str,
+    // Company of the caller, tenants never share cached responses
+    tenant: String,
+    // Path and query string
+    uri: String,
+}
+
+struct CacheEntry {
+    response: StoredResponse,
+    expires: Instant,
+}
+
+/// Successful GET responses of all route groups, bounded by the number of entries.
+pub struct ResponseCache {
+    entries: Mutex<LruCache<CacheKey, CacheEntry>>,
+    // Keyed by route pattern, routes without a TTL are not cached
+    ttls: HashMap<String, Duration>,
+    // Bumped by every invalidation, responses fetched before a write are not stored after it
+    generation: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries).unwrap_or(NonZeroUsize::MIN);
+
+        ResponseCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttls: config.ttls.iter()
+                .filter(|(_, ttl)| **ttl > 0)
+                .map(|(route, ttl)| (route.clone(), Duration::from_secs(*ttl)))
+                .collect(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<StoredResponse> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.response.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, response: StoredResponse, ttl: Duration, generation: u64) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if self.generation.load(Ordering::Acquire) == generation {
+            entries.put(key, CacheEntry { response, expires: Instant::now() + ttl });
+        }
+    }
+
+    // A write may change any list or item of the group, for every tenant
+    fn invalidate(&self, group: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        let keys = entries.iter()
+            .filter(|(key, _)| key.group == group)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            entries.pop(key);
+        }
+        debug!("invalidated {} cached responses of {}", keys.len(), group);
+    }
+}
+
+// Middleware structure
+pub struct ResponseCacheMiddleware {
+    cache: Arc<ResponseCache>,
+    group: &'static str,
+    publisher: Arc<MetricsPublisher>,
+}
+
+impl ResponseCacheMiddleware {
+    pub fn new(cache: Arc<ResponseCache>, group: &'static str, publisher: Arc<MetricsPublisher>) -> Self {
+        ResponseCacheMiddleware { cache, group, publisher }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for ResponseCacheMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = ResponseCacheMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ResponseCacheMiddlewareService {
+            service,
+            cache: Arc::clone(&self.cache),
+            group: self.group,
+            publisher: Arc::clone(&self.publisher),
+        })
+    }
+}
+
+// Middleware logic
+pub struct ResponseCacheMiddlewareService<S> {
+    service: S,
+    cache: Arc<ResponseCache>,
+    group: &'static str,
+    publisher: Arc<MetricsPublisher>,
+}
+
+impl<S, B> Service<ServiceRequest> for ResponseCacheMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let cache = Arc::clone(&self.cache);
+        let group = self.group;
+
+        if req.method() != Method::GET {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                let res = fut.await?;
+                if res.status().is_success() {
+                    cache.invalidate(group);
+                }
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        let route = req.match_pattern().unwrap_or_default();
+        let Some(ttl) = cache.ttls.get(&route).copied() else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        };
+
+        let key = CacheKey {
+            group,
+            tenant: req.extensions().get::<Claims>().map(|c| c.company.clone()).unwrap_or_default(),
+            uri: req.uri().path_and_query().map(|p| p.to_string()).unwrap_or_default(),
+        };
+
+        if let Some(stored) = cache.get(&key) {
+            self.publish(&route, "hit");
+            let mut res = stored.to_response();
+            res.headers_mut().insert(HeaderName::from_static(CACHE_STATUS_HEADER), HeaderValue::from_static("HIT"));
+            return Box::pin(async move { Ok(req.into_response(res).map_into_right_body()) });
+        }
+        self.publish(&route, "miss");
+
+        let generation = cache.generation.load(Ordering::Acquire);
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            if res.status() != StatusCode::OK {
+                return Ok(res.map_into_left_body());
+            }
+
+            let (mut res, stored) = StoredResponse::capture(res).await?;
+            cache.insert(key, stored, ttl, generation);
+            res.headers_mut().insert(HeaderName::from_static(CACHE_STATUS_HEADER), HeaderValue::from_static("MISS"));
+
+            Ok(res.map_into_right_body())
+        })
+    }
+}
+
+impl<S> ResponseCacheMiddlewareService<S> {
+    fn publish(&self, route: &str, result: &str) {
+        self.publisher.spawn_publish(format!("response_cache,group={},route={},result={} count=1", self.group, route, result));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::config::SecurityHeadersConfig;
+use crate::middleware::trusted_proxies::ClientInfo;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, STRICT_TRANSPORT_SECURITY};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use log::error;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+const DEFAULT_HEADERS: [(&str, &str); 5] = [
+    ("strict-transport-security", "max-age=31536000; includeSubDomains"),
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "DENY"),
+    ("referrer-policy", "no-referrer"),
+    ("content-security-policy", "default-src 'none'; frame-ancestors 'none'"),
+];
+
+// `None` removes a default header
+type HeaderSet = Vec<(HeaderName, Option<HeaderValue>)>;
+
+#[derive(Default)]
+struct CompiledHeaders {
+    defaults: HeaderSet,
+    routes: HashMap<String, HeaderSet>,
+}
+
+/// Security headers added to every response, with overrides per route pattern.
+pub struct SecurityHeaders {
+    compiled: RwLock<CompiledHeaders>,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: &SecurityHeadersConfig) -> Self {
+        let headers = SecurityHeaders { compiled: RwLock::new(CompiledHeaders::default()) };
+        headers.replace(config);
+        headers
+    }
+
+    pub fn replace(&self, config: &SecurityHeadersConfig) {
+        let mut defaults = DEFAULT_HEADERS.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        defaults.extend(config.headers.iter().map(|(k, v)| (k.to_ascii_lowercase(), v.clone())));
+
+        let compiled = CompiledHeaders {
+            defaults: compile(&defaults),
+            routes: config.routes.iter()
+                .map(|(route, headers)| (route.clone(), compile(headers)))
+                .collect(),
+        };
+
+        *self.compiled.write().unwrap_or_else(|e| e.into_inner()) = compiled;
+    }
+
+    fn apply(&self, route: Option<&str>, https: bool, res: &mut actix_web::HttpResponse<impl Sized>) {
+        let compiled = self.compiled.read().unwrap_or_else(|e| e.into_inner());
+        let overrides = route.and_then(|r| compiled.routes.get(r));
+
+        let mut headers = compiled.defaults.clone();
+        for (name, value) in overrides.into_iter().flatten() {
+            headers.retain(|(n, _)| n != name);
+            headers.push((name.clone(), value.clone()));
+        }
+
+        for (name, value) in headers {
+            // HSTS is ignored by browsers on plain HTTP responses
+            if name == STRICT_TRANSPORT_SECURITY && !https {
+                continue;
+            }
+            if let Some(value) = value {
+                // Headers set by the handler win over the policy
+                if !res.headers().contains_key(&name) {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+// An empty value disables the header
+fn compile(headers: &HashMap<String, String>) -> HeaderSet {
+    headers.iter()
+        .filter_map(|(name, value)| {
+            let Ok(header_name) = HeaderName::try_from(name.as_str()) else {
+                error!("ignoring invalid security header name {}", name);
+                return None;
+            };
+            if value.is_empty() {
+                return Some((header_name, None));
+            }
+            match HeaderValue::from_str(value) {
+                Ok(value) => Some((header_name, Some(value))),
+                Err(_) => {
+                    error!("ignoring invalid value of security header {}", name);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Middleware structure
+pub struct SecurityHeadersMiddleware {
+    headers: Arc<SecurityHeaders>,
+}
+
+impl SecurityHeadersMiddleware {
+    pub fn new(headers: Arc<SecurityHeaders>) -> Self {
+        SecurityHeadersMiddleware { headers }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeadersMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SecurityHeadersMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddlewareService {
+            service,
+            headers: Arc::clone(&self.headers),
+        })
+    }
+}
+
+// Middleware logic
+pub struct SecurityHeadersMiddlewareService<S> {
+    service: S,
+    headers: Arc<SecurityHeaders>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let route = req.match_pattern();
+        let https = req.extensions().get::<ClientInfo>()
+            .map(|c| c.scheme == "https")
+            .unwrap_or(req.app_config().secure());
+        let headers = Arc::clone(&self.headers);
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            // Errors of the inner services only become responses after this middleware, they get the headers themselves
+            let mut res = match fut.await {
+                Ok(res) => res,
+                Err(error) => return Err(WithSecurityHeaders { error, headers, route, https }.into()),
+            };
+            headers.apply(route.as_deref(), https, res.response_mut());
+
+            Ok(res)
+        })
+    }
+}
+
+// Inner error whose response carries the security headers
+struct WithSecurityHeaders {
+    error: Error,
+    headers: Arc<SecurityHeaders>,
+    route: Option<String>,
+    https: bool,
+}
+
+impl fmt::Debug for WithSecurityHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for WithSecurityHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl ResponseError for WithSecurityHeaders {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = self.error.error_response();
+        self.headers.apply(self.route.as_deref(), self.https, &mut res);
+        res
+    }
+}
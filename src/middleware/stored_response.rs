@@ -0,0 +1,44 @@
+use crate::error::api_error;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpResponse};
+
+/// Fully buffered response, kept by middlewares which answer later requests without calling the handler.
+#[derive(Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub body: Bytes,
+}
+
+impl StoredResponse {
+    /// Buffers the body of the response, returning the response to send on along with its copy.
+    pub async fn capture<B>(res: ServiceResponse<B>) -> Result<(ServiceResponse<BoxBody>, StoredResponse), Error>
+    where
+        B: MessageBody,
+    {
+        let (req, res) = res.into_parts();
+        let (res, body) = res.into_parts();
+        let body = body::to_bytes(body).await
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.into().to_string()))?;
+
+        let stored = StoredResponse {
+            status: res.status(),
+            headers: res.headers().iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+            body: body.clone(),
+        };
+
+        Ok((ServiceResponse::new(req, res.set_body(body).map_into_boxed_body()), stored))
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            res.append_header((name.clone(), value.clone()));
+        }
+        res.body(self.body.clone())
+    }
+}
@@ -0,0 +1,290 @@
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Client address, scheme and host as seen in front of our trusted proxies.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub scheme: String,
+    pub host: String,
+}
+
+// Middleware structure
+pub struct TrustedProxies {
+    proxies: Arc<Vec<IpNet>>,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Arc<Vec<IpNet>>) -> Self {
+        TrustedProxies { proxies }
+    }
+}
+
+// Implement `Transform` for middleware
+impl<S, B> Transform<S, ServiceRequest> for TrustedProxies
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TrustedProxiesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TrustedProxiesMiddleware {
+            service,
+            proxies: Arc::clone(&self.proxies),
+        })
+    }
+}
+
+// Middleware logic
+pub struct TrustedProxiesMiddleware<S> {
+    service: S,
+    proxies: Arc<Vec<IpNet>>,
+}
+
+impl<S, B> Service<ServiceRequest> for TrustedProxiesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let client_info = resolve_client_info(&req, &self.proxies);
+        req.extensions_mut().insert(client_info);
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn is_trusted(proxies: &[IpNet], ip: &IpAddr) -> bool {
+    proxies.iter().any(|net| net.contains(ip))
+}
+
+fn resolve_client_info(req: &ServiceRequest, proxies: &[IpNet]) -> ClientInfo {
+    // `connection_info` already honours forwarding headers from anyone, so start from the raw connection
+    let peer = req.peer_addr().map(|a| a.ip());
+    let config = req.app_config();
+
+    let mut info = ClientInfo {
+        ip: peer,
+        scheme: if config.secure() { "https" } else { "http" }.to_owned(),
+        host: req.headers()
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or(config.host())
+            .to_owned(),
+    };
+
+    // Forwarding headers are only believed when they were set by one of our own proxies
+    match peer {
+        Some(peer) if is_trusted(proxies, &peer) => {}
+        _ => return info,
+    }
+
+    let hops = if req.headers().contains_key(header::FORWARDED) {
+        forwarded_hops(req.headers())
+    } else {
+        x_forwarded_hops(req.headers())
+    };
+
+    // Walk from the closest hop and stop at the first address we do not trust
+    for hop in hops.iter().rev() {
+        if let Some(proto) = &hop.proto {
+            info.scheme = proto.clone();
+        }
+        if let Some(host) = &hop.host {
+            info.host = host.clone();
+        }
+        match hop.ip {
+            Some(ip) => {
+                info.ip = Some(ip);
+                if !is_trusted(proxies, &ip) {
+                    break;
+                }
+            }
+            // Unknown or obfuscated node, nothing further left can be trusted
+            None => break,
+        }
+    }
+
+    info
+}
+
+// RFC 7239: `Forwarded: for=192.0.2.60;proto=http;host=example.com, for="[2001:db8::1]:4711"`
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    headers.get_all(header::FORWARDED)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in element.split(';') {
+                let Some((name, value)) = pair.split_once('=') else { continue };
+                let value = value.trim().trim_matches('"');
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.ip = parse_node(value),
+                    "proto" => hop.proto = Some(value.to_ascii_lowercase()),
+                    "host" => hop.host = Some(value.to_owned()),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+// Proto and host are not chained, they were written by the proxy that connected to us
+fn x_forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    let first_value = |name: &str| headers.get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty());
+
+    let mut hops = headers.get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| Hop { ip: parse_node(v.trim()), ..Hop::default() })
+        .collect::<Vec<_>>();
+
+    if let Some(last) = hops.last_mut() {
+        last.proto = first_value("x-forwarded-proto").map(|p| p.to_ascii_lowercase());
+        last.host = first_value("x-forwarded-host");
+    }
+
+    hops
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` and `[2001:db8::1]:80`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>().ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// Parses a trusted proxy entry, a single address is treated as a host network.
+pub fn parse_proxy(value: &str) -> Option<IpNet> {
+    value.parse::<IpNet>().ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn proxies() -> Vec<IpNet> {
+        ["10.0.0.0/8", "fd00::/8"].iter().filter_map(|p| parse_proxy(p)).collect()
+    }
+
+    fn client_info(peer: &str, headers: &[(&str, &str)]) -> ClientInfo {
+        let mut req = TestRequest::default().peer_addr(peer.parse().unwrap()).insert_header((header::HOST, "gateway.internal"));
+        for header in headers {
+            req = req.append_header(*header);
+        }
+        resolve_client_info(&req.to_srv_request(), &proxies())
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn headers_of_untrusted_peers_are_ignored() {
+        let info = client_info("203.0.113.9:5000", &[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=198.51.100.2;host=shop.example.com"),
+        ]);
+
+        assert_eq!(info.ip, ip("203.0.113.9"));
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "gateway.internal");
+    }
+
+    #[test]
+    fn spoofed_leftmost_hops_are_not_believed() {
+        // The client sent `1.1.1.1`, our proxy appended the address it saw
+        let info = client_info("10.0.0.2:5000", &[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.0.0.3")]);
+
+        assert_eq!(info.ip, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn obfuscated_hops_stop_the_walk() {
+        let info = client_info("10.0.0.2:5000", &[("forwarded", "for=198.51.100.7, for=_hidden, for=10.0.0.3")]);
+
+        assert_eq!(info.ip, ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_headers() {
+        let info = client_info("10.0.0.2:5000", &[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("x-forwarded-host", "spoofed.example.com"),
+            ("forwarded", "for=198.51.100.2;proto=https;host=shop.example.com"),
+        ]);
+
+        assert_eq!(info.ip, ip("198.51.100.2"));
+        assert_eq!(info.scheme, "https");
+        assert_eq!(info.host, "shop.example.com");
+    }
+
+    #[test]
+    fn x_forwarded_proto_and_host_come_from_the_closest_proxy() {
+        let info = client_info("10.0.0.2:5000", &[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("x-forwarded-proto", "HTTPS, http"),
+            ("x-forwarded-host", "shop.example.com"),
+        ]);
+
+        assert_eq!(info.ip, ip("198.51.100.1"));
+        assert_eq!(info.scheme, "https");
+        assert_eq!(info.host, "shop.example.com");
+    }
+
+    #[test]
+    fn ipv6_hops_with_ports_are_parsed() {
+        let info = client_info("[fd00::1]:5000", &[("forwarded", "for=\"[2001:db8::1]:4711\", for=\"[fd00::2]\"")]);
+        assert_eq!(info.ip, ip("2001:db8::1"));
+
+        let info = client_info("[fd00::1]:5000", &[("x-forwarded-for", "[2001:db8::2]:80, fd00::3")]);
+        assert_eq!(info.ip, ip("2001:db8::2"));
+    }
+
+    #[test]
+    fn nodes_with_and_without_ports_are_parsed() {
+        assert_eq!(parse_node("192.0.2.60"), ip("192.0.2.60"));
+        assert_eq!(parse_node("192.0.2.60:80"), ip("192.0.2.60"));
+        assert_eq!(parse_node("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_node("[2001:db8::1]"), ip("2001:db8::1"));
+        assert_eq!(parse_node("[2001:db8::1]:80"), ip("2001:db8::1"));
+        assert_eq!(parse_node("unknown"), None);
+    }
+}
@@ -1,3 +1,6 @@
 pub mod auth_models;
 pub mod product_models;
-pub mod order_models;
\ No newline at end of file
+pub mod order_models;
+pub mod error_models;
+pub mod page_models;
+pub mod money_models;
\ No newline at end of file
@@ -1,13 +1,17 @@
 use serde::{Serialize, Deserialize};
+use validator::Validate;
+use crate::validation::validate_password;
 
 #[derive(Serialize)]
 pub struct IsAdminResponse {
     pub is_admin: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct LoginRequest {
+    #[validate(email, length(max = 254))]
     pub email: String,
+    #[validate(length(min = 1, max = 128))]
     pub password: String
 }
 
@@ -17,9 +21,11 @@ pub struct LoginResponse {
     pub token: String
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct RegisterRequest {
+    #[validate(email, length(max = 254))]
     pub email: String,
+    #[validate(length(min = 8, max = 128), custom(function = "validate_password"))]
     pub password: String
 }
 
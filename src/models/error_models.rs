@@ -0,0 +1,11 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub request_id: Option<String>,
+    /// Messages keyed by the path of the invalid field, e.g. `items[0].quantity`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<BTreeMap<String, Vec<String>>>,
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use crate::error::Error;
+use crate::validation::validate_currency;
+
+/// Amount in minor units of an ISO 4217 currency, `{"amount": 1999, "currency": "EUR"}` is 19.99 EUR
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Validate)]
+pub struct Money {
+    #[validate(range(min = 0))]
+    pub amount: i64,
+    #[validate(custom(function = "validate_currency"))]
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(amount: i64, currency: String) -> Self {
+        Money { amount, currency }
+    }
+
+    pub fn checked_mul(&self, quantity: i64) -> Result<Money, Error> {
+        self.amount.checked_mul(quantity)
+            .map(|amount| Money::new(amount, self.currency.clone()))
+            .ok_or_else(|| Error::AmountOverflow(self.currency.clone()))
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, Error> {
+        if self.currency != other.currency {
+            return Err(Error::CurrencyMismatch(self.currency.clone(), other.currency.clone()));
+        }
+        self.amount.checked_add(other.amount)
+            .map(|amount| Money::new(amount, self.currency.clone()))
+            .ok_or_else(|| Error::AmountOverflow(self.currency.clone()))
+    }
+}
+
+/// Sums the amounts per currency, ordered by currency code.
+pub fn totals<'a>(amounts: impl IntoIterator<Item = &'a Money>) -> Result<Vec<Money>, Error> {
+    let mut totals: BTreeMap<&str, Money> = BTreeMap::new();
+    for money in amounts {
+        let total = match totals.get(money.currency.as_str()) {
+            Some(total) => total.checked_add(money)?,
+            None => money.clone(),
+        };
+        totals.insert(&money.currency, total);
+    }
+    Ok(totals.into_values().collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn money(amount: i64, currency: &str) -> Money {
+        Money::new(amount, currency.to_owned())
+    }
+
+    #[test]
+    fn totals_sums_per_currency_ordered_by_code() {
+        let amounts = [money(100, "USD"), money(250, "EUR"), money(50, "USD"), money(1, "CHF")];
+
+        let totals = totals(&amounts).unwrap();
+
+        assert_eq!(totals, vec![money(1, "CHF"), money(250, "EUR"), money(150, "USD")]);
+    }
+
+    #[test]
+    fn totals_of_nothing_is_empty() {
+        assert_eq!(totals(Vec::<&Money>::new()).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn totals_rejects_overflowing_sum() {
+        let amounts = [money(i64::MAX, "EUR"), money(1, "EUR")];
+
+        assert!(matches!(totals(&amounts), Err(Error::AmountOverflow(currency)) if currency == "EUR"));
+    }
+
+    #[test]
+    fn totals_keeps_large_amounts_of_other_currencies_apart() {
+        let amounts = [money(i64::MAX, "EUR"), money(1, "USD")];
+
+        assert_eq!(totals(&amounts).unwrap(), vec![money(i64::MAX, "EUR"), money(1, "USD")]);
+    }
+
+    #[test]
+    fn checked_mul_rejects_overflow() {
+        assert_eq!(money(1999, "EUR").checked_mul(3).unwrap(), money(5997, "EUR"));
+        assert!(matches!(money(i64::MAX / 2 + 1, "EUR").checked_mul(2), Err(Error::AmountOverflow(_))));
+    }
+
+    #[test]
+    fn checked_add_rejects_mixed_currencies() {
+        let result = money(1, "EUR").checked_add(&money(1, "USD"));
+
+        assert!(matches!(result, Err(Error::CurrencyMismatch(left, right)) if left == "EUR" && right == "USD"));
+    }
+}
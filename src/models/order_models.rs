@@ -1,20 +1,33 @@
+use std::borrow::Cow;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+use crate::models::money_models::{totals, Money};
+use crate::validation::{validate_page, validate_range, validate_sort};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_order_request"))]
 pub struct OrderRequest {
+    #[validate(length(min = 1, max = 100), nested)]
     pub items: Vec<OrderLineItems>
 }
 
-#[derive(Debug, Serialize)]
-pub struct OrderResponse {
-    pub order_number: String
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct OrderLineItems {
+    #[validate(length(min = 1, max = 64))]
+    pub sku_code: String,
+    /// Must match the catalogue price, the gateway fills in the catalogue price when it is left out
+    #[validate(nested)]
+    pub price: Option<Money>,
+    #[validate(range(min = 1, max = 10000))]
+    pub quantity: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct OrderLineItems {
+/// Line item whose price is the catalogue price of its product
+#[derive(Debug)]
+pub struct PricedLineItem {
     pub sku_code: String,
-    pub price: i64,
+    pub price: Money,
     pub quantity: i64,
 }
 
@@ -23,10 +36,166 @@ pub struct OrderEntityResponse {
     pub order_id: i64,
     pub order_number: String,
     pub created_at: Option<DateTime<Utc>>,
-    pub items: Vec<OrderLineItems>
+    pub items: Vec<OrderLineItemResponse>,
+    pub status: OrderStatus,
+    /// Sum of the line totals per currency, one entry unless the backend stored mixed currencies,
+    /// `null` when a line total or the sum does not fit into 64 bits
+    pub totals: Option<Vec<Money>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrderLineItemResponse {
+    pub sku_code: String,
+    pub price: Money,
+    pub quantity: i64,
+    /// `price` times `quantity`, `null` when it does not fit into 64 bits
+    pub line_total: Option<Money>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// Sent by backends which do not track the status yet
+    Unknown,
+    Pending,
+    Paid,
+    Shipped,
+    Cancelled,
 }
 
+/// Relations which `expand` can embed into an order
+const ORDER_EXPANSIONS: [&str; 1] = ["products"];
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct OrderQuery {
+    /// Comma separated relations to embed, only `products` so far
+    #[validate(custom(function = "validate_order_expand"))]
+    pub expand: Option<String>,
+}
+
+impl OrderQuery {
+    pub fn expands(&self, relation: &str) -> bool {
+        self.expand.as_deref().is_some_and(|e| e.split(',').any(|r| r.trim() == relation))
+    }
+}
+
+/// Order with the catalogue data of its products, returned for `expand=products`
 #[derive(Debug, Serialize)]
-pub struct DeleteOrderResponse {
-    pub is_deleted: bool
-}
\ No newline at end of file
+pub struct ExpandedOrderResponse {
+    pub order_id: i64,
+    pub order_number: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub items: Vec<ExpandedLineItem>,
+    pub status: OrderStatus,
+    pub totals: Option<Vec<Money>>,
+    /// SKUs whose product could not be looked up, their items have no `product`
+    pub unavailable_products: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpandedLineItem {
+    pub sku_code: String,
+    pub price: Money,
+    pub quantity: i64,
+    pub line_total: Option<Money>,
+    pub product: Option<LineItemProduct>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineItemProduct {
+    pub name: String,
+    pub description: String,
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_order_list_query"))]
+pub struct OrderListQuery {
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<u32>,
+    #[validate(length(min = 1, max = 512))]
+    pub cursor: Option<String>,
+    /// Sent to the backend as an `int32`, deep pages are reached with `cursor`
+    #[validate(range(max = 100000))]
+    pub offset: Option<u32>,
+    #[validate(custom(function = "validate_order_sort"))]
+    pub sort: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+fn validate_order_sort(sort: &str) -> Result<(), ValidationError> {
+    validate_sort(sort, &["created_at", "order_number"])
+}
+
+fn validate_order_list_query(query: &OrderListQuery) -> Result<(), ValidationError> {
+    validate_page(query.cursor.as_ref(), query.offset)?;
+    validate_range(query.created_after, query.created_before, "created_before")
+}
+
+fn validate_order_expand(expand: &str) -> Result<(), ValidationError> {
+    if expand.split(',').all(|r| ORDER_EXPANSIONS.contains(&r.trim())) {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("expand");
+    error.message = Some(Cow::Owned(format!("must be a comma separated list of {}", ORDER_EXPANSIONS.join(", "))));
+    Err(error)
+}
+
+// Field checks run first, so every price here has a valid currency
+fn validate_order_request(request: &OrderRequest) -> Result<(), ValidationError> {
+    validate_line_totals(request.items.iter().filter_map(|item| item.price.as_ref().map(|price| (price, item.quantity))))
+}
+
+/// The items of an order must be in one currency and their total must not overflow.
+pub fn validate_line_totals<'a>(items: impl IntoIterator<Item = (&'a Money, i64)>) -> Result<(), ValidationError> {
+    let line_totals = items.into_iter()
+        .map(|(price, quantity)| price.checked_mul(quantity))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| items_error(e.to_string()))?;
+
+    match totals(&line_totals).map_err(|e| items_error(e.to_string()))?.as_slice() {
+        [_] | [] => Ok(()),
+        totals => {
+            let currencies = totals.iter().map(|total| total.currency.as_str()).collect::<Vec<_>>();
+            Err(items_error(format!("must all be in one currency, got {}", currencies.join(", "))))
+        },
+    }
+}
+
+fn items_error(message: String) -> ValidationError {
+    let mut error = ValidationError::new("items");
+    error.message = Some(Cow::Owned(message));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::money_models::tests::money;
+
+    #[test]
+    fn line_totals_in_one_currency_are_valid() {
+        let (a, b) = (money(1999, "EUR"), money(500, "EUR"));
+
+        assert!(validate_line_totals([(&a, 3), (&b, 1)]).is_ok());
+    }
+
+    #[test]
+    fn line_totals_in_mixed_currencies_are_rejected() {
+        let (a, b) = (money(1999, "EUR"), money(500, "USD"));
+
+        let error = validate_line_totals([(&a, 1), (&b, 1)]).unwrap_err();
+
+        assert_eq!(error.code, "items");
+        assert_eq!(error.message.as_deref(), Some("must all be in one currency, got EUR, USD"));
+    }
+
+    #[test]
+    fn overflowing_line_totals_are_rejected() {
+        let (a, b) = (money(i64::MAX, "EUR"), money(1, "EUR"));
+
+        assert!(validate_line_totals([(&a, 2)]).is_err());
+        assert!(validate_line_totals([(&a, 1), (&b, 1)]).is_err());
+    }
+}
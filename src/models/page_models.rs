@@ -0,0 +1,28 @@
+/// Page size used when the request does not set `limit`
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+
+/// One page of a list endpoint, `next_cursor` is `None` on the last page.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Sort order parsed from `sort=field` or `sort=-field` (descending).
+#[derive(Debug, Default)]
+pub struct Sort {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl Sort {
+    pub fn parse(sort: Option<&str>) -> Self {
+        match sort {
+            Some(sort) => match sort.strip_prefix('-') {
+                Some(field) => Sort { field: field.to_owned(), descending: true },
+                None => Sort { field: sort.to_owned(), descending: false },
+            },
+            None => Sort::default(),
+        }
+    }
+}
\ No newline at end of file
@@ -1,11 +1,17 @@
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+use validator::ValidationError;
+use crate::models::money_models::Money;
+use crate::validation::{validate_currency, validate_page, validate_range, validate_sort};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Validate)]
 pub struct ProductRequest {
+    #[validate(length(min = 1, max = 200))]
     pub name: String,
+    #[validate(length(max = 2000))]
     pub description: String,
-    pub currency: String,
-    pub price: i64
+    #[validate(nested)]
+    pub price: Money,
 }
 
 #[derive(Serialize)]
@@ -13,11 +19,36 @@ pub struct ProductResponse {
     pub id: String,
     pub name: String,
     pub description: String,
-    pub currency: String,
-    pub price: i64
+    pub price: Money,
+    /// Sent as the `ETag` of the product and expected back in `If-Match`
+    pub version: i64,
 }
 
-#[derive(Serialize)]
-pub struct DeleteProductResponse {
-    pub is_deleted: bool
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_product_list_query"))]
+pub struct ProductListQuery {
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<u32>,
+    #[validate(length(min = 1, max = 512))]
+    pub cursor: Option<String>,
+    /// Sent to the backend as an `int32`, deep pages are reached with `cursor`
+    #[validate(range(max = 100000))]
+    pub offset: Option<u32>,
+    #[validate(custom(function = "validate_product_sort"))]
+    pub sort: Option<String>,
+    #[validate(range(min = 0))]
+    pub min_price: Option<i64>,
+    #[validate(range(min = 0))]
+    pub max_price: Option<i64>,
+    #[validate(custom(function = "validate_currency"))]
+    pub currency: Option<String>,
+}
+
+fn validate_product_sort(sort: &str) -> Result<(), ValidationError> {
+    validate_sort(sort, &["name", "price"])
+}
+
+fn validate_product_list_query(query: &ProductListQuery) -> Result<(), ValidationError> {
+    validate_page(query.cursor.as_ref(), query.offset)?;
+    validate_range(query.min_price, query.max_price, "max_price")
 }
\ No newline at end of file
@@ -1,67 +1,313 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use crate::error::Error;
-use actix_web::{error, web, HttpResponse};
+use crate::error::{api_error, Error};
+use actix_web::http::{Method, StatusCode};
+use actix_web::http::header::LINK;
+use actix_web::dev::ResourceDef;
+use actix_web::middleware::Condition;
+use actix_web::{guard, web, HttpRequest, HttpResponse};
 use log::error;
-use reqwest::Client;
 use serde::Serialize;
+use tonic::Code;
 
 pub mod auth_routes;
 mod product_routes;
 mod order_routes;
+mod transcoded_routes;
+mod grpc_web_routes;
 
 use crate::middleware::jwt_validator::JwtValidator;
-use crate::middleware::metrics::MetricsMiddleware;
+use crate::middleware::client_cert::ClientPrincipals;
+use crate::middleware::conditional_get::{CacheHeaders, ConditionalGet};
+use crate::middleware::cors::CorsPolicies;
+use crate::middleware::idempotency::{Idempotency, IdempotencyStore};
+use crate::middleware::ip_filter::{IpFilter, IpFilters};
+use crate::middleware::metrics::{MetricsMiddleware, MetricsPublisher};
+use crate::middleware::response_cache::{ResponseCache, ResponseCacheMiddleware};
+use crate::models::page_models::Page;
 use crate::routes::auth_routes::{is_admin, login, register};
-use crate::routes::order_routes::{delete_order, get_order_list, place_order};
-use crate::routes::product_routes::{delete_product, get_list_products, save_product};
+use crate::routes::order_routes::{cancel_order, get_order, get_order_list, place_order};
+use crate::routes::product_routes::{delete_product, get_list_products, get_product, patch_product, save_product, update_product};
+use crate::routes::grpc_web_routes::grpc_web;
+use crate::routes::transcoded_routes::{call_rpc, transcode};
+use crate::services::transcoding_service::{BackendRpc, HttpBinding};
+use crate::validation::json_config;
+
+/// Response header carrying the cursor of the next page of a list endpoint
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Route group of administrative operations, its CIDR lists come from `[ip_filters.admin]` in the config file
+pub const ADMIN_IP_FILTER: &str = "admin";
+
+// Methods of the administrative operations on a resource, filtered before the token is checked
+const ADMIN_WRITE_METHODS: [&str; 4] = ["POST", "PUT", "PATCH", "DELETE"];
+
+// Methods allowed by CORS unless the `[cors.<group>]` section of the config file lists them
+const AUTH_METHODS: [&str; 2] = ["GET", "POST"];
+
+const PRODUCT_METHODS: [&str; 5] = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+const ORDER_METHODS: [&str; 2] = ["GET", "POST"];
+
+// JSON body limits in bytes unless the `[body_limits]` section of the config file overrides the route
+const AUTH_BODY_LIMIT: usize = 4 * 1024;
+
+const PRODUCT_BODY_LIMIT: usize = 16 * 1024;
+
+const ORDER_BODY_LIMIT: usize = 64 * 1024;
+
+const TRANSCODED_BODY_LIMIT: usize = 64 * 1024;
+
+const GRPC_WEB_BODY_LIMIT: usize = 4 * 1024 * 1024;
+
+// RPCs of the compiled backends served over gRPC-Web, writes with IP filters, idempotency, price checks
+// or cache invalidation on their REST route are left to that route
+const GRPC_WEB_RPCS: [&str; 7] = [
+    "auth.Auth.Login",
+    "auth.Auth.Register",
+    "auth.Auth.IsAdmin",
+    "product.Product.GetProductList",
+    "product.Product.GetProduct",
+    "order.Order.GetOrderList",
+    "order.Order.GetOrder",
+];
+
+// RPCs of the compiled backends a google.api.http binding may call, writes need the validation, idempotency,
+// price checks and cache invalidation of their hand-written route
+const TRANSCODED_RPCS: [&str; 5] = [
+    "auth.Auth.IsAdmin",
+    "product.Product.GetProductList",
+    "product.Product.GetProduct",
+    "order.Order.GetOrderList",
+    "order.Order.GetOrder",
+];
+
+// gRPC-Web RPCs callable without a token, like `/auth/login` and `/auth/register`
+const PUBLIC_RPCS: [&str; 2] = ["auth.Auth.Login", "auth.Auth.Register"];
+
+/// Route group of the RPCs of backends configured in `[dynamic_backends]`
+pub const DYNAMIC_ROUTE_GROUP: &str = "grpc";
+
+// Routes registered by `init_routes` with their methods, transcoded bindings must not take them over
+const HAND_WRITTEN_ROUTES: [(&str, &[&str]); 9] = [
+    ("/auth/is_admin/{id}", &["GET"]),
+    ("/auth/login", &["POST"]),
+    ("/auth/register", &["POST"]),
+    ("/products", &["GET", "POST"]),
+    ("/products/{id}", &["GET", "PUT", "PATCH", "DELETE"]),
+    ("/orders", &["GET", "POST"]),
+    ("/orders/{id}", &["GET"]),
+    ("/orders/{id}/cancel", &["POST"]),
+    ("/rpc/{backend}/{service}/{method}", &["POST"]),
+];
+
+/// Shared state the route middlewares are built from, created once and handed to every worker.
+#[derive(Clone)]
+pub struct RouteState {
+    pub secret: Arc<String>,
+    pub metrics: Arc<MetricsPublisher>,
+    pub ip_filters: Arc<IpFilters>,
+    pub cors: Arc<CorsPolicies>,
+    pub principals: Arc<ClientPrincipals>,
+    pub body_limits: Arc<HashMap<String, usize>>,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub response_cache: Arc<ResponseCache>,
+    pub cache_headers: Arc<CacheHeaders>,
+    pub http_bindings: Arc<Vec<HttpBinding>>,
+    /// Path prefix of the gRPC-Web routes, `None` disables gRPC-Web
+    pub grpc_web_prefix: Option<String>,
+    pub backend_rpcs: Arc<Vec<BackendRpc>>,
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig, state: &RouteState) {
+    let RouteState { secret, metrics, ip_filters, cors, principals, body_limits, idempotency, response_cache, cache_headers, http_bindings, grpc_web_prefix, backend_rpcs } = state;
+    let body_limit = |route: &str, default: usize| body_limits.get(route).copied().unwrap_or(default);
+    let product_body_limit = body_limit("/products", PRODUCT_BODY_LIMIT);
+    let order_body_limit = body_limit("/orders", ORDER_BODY_LIMIT);
+
+    // Registered first and guarded by their method, so a binding can share its path with a hand-written route,
+    // preflight requests of the path are answered here for the methods of both
+    for binding in http_bindings.iter() {
+        let (group, group_methods) = route_group(&binding.backend);
+        let mut methods = group_methods.to_vec();
+        if !methods.contains(&binding.method.as_str()) {
+            methods.push(binding.method.as_str());
+        }
+        cfg.service(
+            web::resource(binding.pattern.as_str())
+                .guard(guard::Any(guard::Method(binding.method.clone())).or(guard::Options()))
+                .app_data(web::Data::new(binding.clone()))
+                .app_data(web::PayloadConfig::new(body_limit(&binding.template, TRANSCODED_BODY_LIMIT)))
+                .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), group))
+                .wrap(Condition::new(binding.method != Method::GET, IpFilter::new(Arc::clone(ip_filters), ADMIN_IP_FILTER, Arc::clone(metrics))))
+                .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+                .wrap(cors.build(group, &methods))
+                .route(web::route().method(binding.method.clone()).to(transcode))
+        );
+    }
+
+    // gRPC-Web clients send the whole request in one body, so client streaming RPCs are left out,
+    // dynamic backends expose all of their RPCs like `/rpc/{backend}/{service}/{method}`
+    if let Some(prefix) = grpc_web_prefix {
+        let grpc_web_body_limit = body_limit(prefix, GRPC_WEB_BODY_LIMIT);
+        for rpc in backend_rpcs.iter().filter(|rpc| !rpc.rpc.is_client_streaming()) {
+            let (group, group_methods) = route_group(&rpc.backend);
+            if group != DYNAMIC_ROUTE_GROUP && !GRPC_WEB_RPCS.contains(&rpc.rpc.full_name()) {
+                continue;
+            }
+            let mut methods = group_methods.to_vec();
+            if !methods.contains(&"POST") {
+                methods.push("POST");
+            }
+            let public = PUBLIC_RPCS.contains(&rpc.rpc.full_name());
+            cfg.service(
+                web::resource(format!("{}/{}/{}", prefix, rpc.rpc.parent_service().full_name(), rpc.rpc.name()))
+                    .app_data(web::Data::new(rpc.clone()))
+                    .app_data(web::PayloadConfig::new(grpc_web_body_limit))
+                    .wrap(Condition::new(!public, JwtValidator::new(Arc::clone(secret), Arc::clone(principals), group)))
+                    .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+                    .wrap(cors.build(group, &methods))
+                    .route(web::post().to(grpc_web))
+            );
+        }
+    }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig, secret: Arc<String>, influxdb_client: Arc<Client>, token: Arc<String>, url: Arc<String>, org: Arc<String>, bucket: Arc<String>) {
     cfg.service(
         web::resource("/auth/is_admin/{id}")
-            .wrap(JwtValidator::new(Arc::clone(&secret)))
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "auth"))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("auth", &AUTH_METHODS))
             .route(web::get().to(is_admin))
     )
     .service(
         web::resource("/auth/login")
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
+            .app_data(json_config(body_limit("/auth/login", AUTH_BODY_LIMIT)))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("auth", &AUTH_METHODS))
             .route(web::post().to(login))
     )
     .service(
         web::resource("/auth/register")
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
+            .app_data(json_config(body_limit("/auth/register", AUTH_BODY_LIMIT)))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("auth", &AUTH_METHODS))
             .route(web::post().to(register))
     )
     .service(
         web::resource("/products")
-            .wrap(JwtValidator::new(Arc::clone(&secret)))
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
-            .route(web::post().to(save_product))
+            .app_data(json_config(product_body_limit))
+            .wrap(ResponseCacheMiddleware::new(Arc::clone(response_cache), "products", Arc::clone(metrics)))
+            .wrap(ConditionalGet::new(Arc::clone(cache_headers)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "products"))
+            .wrap(IpFilter::new(Arc::clone(ip_filters), ADMIN_IP_FILTER, Arc::clone(metrics)).methods(&ADMIN_WRITE_METHODS))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("products", &PRODUCT_METHODS))
+            .route(web::post().to(save_product).wrap(Idempotency::new(Arc::clone(idempotency), product_body_limit)))
             .route(web::get().to(get_list_products))
     )
     .service(
         web::resource("/products/{id}")
-            .wrap(JwtValidator::new(Arc::clone(&secret)))
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
+            .app_data(json_config(body_limit("/products/{id}", PRODUCT_BODY_LIMIT)))
+            .wrap(ResponseCacheMiddleware::new(Arc::clone(response_cache), "products", Arc::clone(metrics)))
+            .wrap(ConditionalGet::new(Arc::clone(cache_headers)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "products"))
+            .wrap(IpFilter::new(Arc::clone(ip_filters), ADMIN_IP_FILTER, Arc::clone(metrics)).methods(&ADMIN_WRITE_METHODS))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("products", &PRODUCT_METHODS))
+            .route(web::get().to(get_product))
+            .route(web::put().to(update_product))
+            .route(web::patch().to(patch_product))
             .route(web::delete().to(delete_product))
     )
     .service(
         web::resource("/orders")
-            .wrap(JwtValidator::new(Arc::clone(&secret)))
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
-            .route(web::post().to(place_order))
+            .app_data(json_config(order_body_limit))
+            .wrap(ConditionalGet::new(Arc::clone(cache_headers)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "orders"))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("orders", &ORDER_METHODS))
+            .route(web::post().to(place_order).wrap(Idempotency::new(Arc::clone(idempotency), order_body_limit)))
             .route(web::get().to(get_order_list))
     )
     .service(
         web::resource("/orders/{id}")
-            .wrap(JwtValidator::new(Arc::clone(&secret)))
-            .wrap(MetricsMiddleware::new(Arc::clone(&influxdb_client), Arc::clone(&token), Arc::clone(&url), Arc::clone(&org), Arc::clone(&bucket)))
-            .route(web::delete().to(delete_order))
+            .wrap(ConditionalGet::new(Arc::clone(cache_headers)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "orders"))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("orders", &ORDER_METHODS))
+            .route(web::get().to(get_order))
+    )
+    .service(
+        web::resource("/orders/{id}/cancel")
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), "orders"))
+            .wrap(IpFilter::new(Arc::clone(ip_filters), ADMIN_IP_FILTER, Arc::clone(metrics)).methods(&ADMIN_WRITE_METHODS))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build("orders", &ORDER_METHODS))
+            .route(web::post().to(cancel_order))
+    )
+    .service(
+        web::resource("/rpc/{backend}/{service}/{method}")
+            .app_data(web::PayloadConfig::new(body_limit("/rpc", TRANSCODED_BODY_LIMIT)))
+            .wrap(JwtValidator::new(Arc::clone(secret), Arc::clone(principals), DYNAMIC_ROUTE_GROUP))
+            .wrap(MetricsMiddleware::new(Arc::clone(metrics)))
+            .wrap(cors.build(DYNAMIC_ROUTE_GROUP, &["POST"]))
+            .route(web::post().to(call_rpc))
     )
     .default_service(web::to(HttpResponse::NotFound))
     ;
 }
 
+/// Fails for bindings answering a method of a hand-written route, they are registered first and would skip
+/// the caching, validation and filters of that route, and for bindings of compiled backends calling a write RPC.
+pub fn check_bindings(bindings: &[HttpBinding]) -> Result<(), Error> {
+    for binding in bindings {
+        let (group, _) = route_group(&binding.backend);
+        if group != DYNAMIC_ROUTE_GROUP && !TRANSCODED_RPCS.contains(&binding.rpc.full_name()) {
+            return Err(Error::Descriptor(format!("google.api.http of {}: only reads of the {} backend can be transcoded", binding.rpc.full_name(), binding.backend)));
+        }
+
+        let binding_def = ResourceDef::new(binding.pattern.as_str());
+        let routes = HAND_WRITTEN_ROUTES.iter().filter(|(_, methods)| methods.contains(&binding.method.as_str()));
+        for (route, _) in routes {
+            if binding_def.is_match(&sample_path(route)) || ResourceDef::new(*route).is_match(&sample_path(&binding.template)) {
+                return Err(Error::Descriptor(format!("google.api.http of {}: {} {} collides with the route {}", binding.rpc.full_name(), binding.method, binding.template, route)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Path matched by a path template, every variable stands for its literal segments or `x`
+fn sample_path(template: &str) -> String {
+    let mut path = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        path.push_str(&rest[..start]);
+        let segments = rest[start + 1..end].split_once('=').map_or("*", |(_, segments)| segments);
+        let sample = segments.split('/').map(|segment| match segment {
+            "*" | "**" => "x",
+            literal => literal,
+        }).collect::<Vec<_>>();
+        path.push_str(&sample.join("/"));
+        rest = &rest[end + 1..];
+    }
+    path.push_str(rest);
+    path
+}
+
+// Route group and CORS methods of the backend serving a transcoded RPC
+fn route_group(backend: &str) -> (&'static str, &'static [&'static str]) {
+    match backend {
+        "auth" => ("auth", &AUTH_METHODS),
+        "product" => ("products", &PRODUCT_METHODS),
+        "order" => ("orders", &ORDER_METHODS),
+        _ => (DYNAMIC_ROUTE_GROUP, &[]),
+    }
+}
+
 pub fn handle_result<T, F>(res: Result<T, Error>, log_f: F) -> actix_web::Result<HttpResponse>
 where
     T: Serialize,
@@ -72,13 +318,63 @@ where
             log_f(&t);
             Ok(HttpResponse::Ok().json(t))
         },
-        Err(Error::GrpcStatus { input, status }) => {
+        Err(e) => Err(error_response(e)),
+    }
+}
+
+// Backend failures the caller can act upon keep their meaning, anything else is an internal error
+fn grpc_status_code(code: Code) -> StatusCode {
+    match code {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists => StatusCode::CONFLICT,
+        // Invalid state transitions, e.g. cancelling a shipped order
+        Code::FailedPrecondition => StatusCode::CONFLICT,
+        // Optimistic concurrency conflicts, the resource changed since the version in `If-Match`
+        Code::Aborted => StatusCode::PRECONDITION_FAILED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error_response(e: Error) -> actix_web::Error {
+    match e {
+        Error::GrpcStatus { input, status } => {
             error!("{}, {}", input, status);
-            Err(error::ErrorInternalServerError(format!("{}, {}", input, status)))
+            api_error(grpc_status_code(status.code()), format!("{}, {}", input, status))
         },
-        Err(e) => {
+        e => {
             error!("{}", e);
-            Err(error::ErrorInternalServerError(format!("{}", e)))
+            api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e))
         }
     }
 }
+
+/// Like `handle_result`, the body stays a plain array, the next page is announced in the `Link` and `X-Next-Cursor` headers.
+pub fn handle_page<T, F>(req: &HttpRequest, res: Result<Page<T>, Error>, log_f: F) -> actix_web::Result<HttpResponse>
+where
+    T: Serialize,
+    F: Fn(&Page<T>),
+{
+    let page = res.map_err(error_response)?;
+    log_f(&page);
+
+    let mut response = HttpResponse::Ok();
+    if let Some(cursor) = &page.next_cursor {
+        response.insert_header((NEXT_CURSOR_HEADER, cursor.as_str()));
+        response.insert_header((LINK, format!("<{}>; rel=\"next\"", next_page_url(req, cursor))));
+    }
+
+    Ok(response.json(page.items))
+}
+
+// Keeps the filters and sorting of the request, the cursor replaces any cursor or offset.
+// Relative, so a cached page never carries the host of the caller who filled the cache
+fn next_page_url(req: &HttpRequest, cursor: &str) -> String {
+    let mut query = req.query_string().split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("cursor=") && !pair.starts_with("offset="))
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    query.push(serde_urlencoded::to_string([("cursor", cursor)]).unwrap_or_default());
+
+    format!("{}?{}", req.path(), query.join("&"))
+}
@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse};
 use log::info;
 use crate::models::auth_models::{LoginRequest, RegisterRequest};
 use crate::routes::handle_result;
+use crate::validation::ValidatedJson;
 use crate::services::auth_service::AuthService;
 
 pub async fn is_admin(service: web::Data<AuthService>, path: web::Path<(String,)>) -> actix_web::Result<HttpResponse> {
@@ -13,7 +14,7 @@ pub async fn is_admin(service: web::Data<AuthService>, path: web::Path<(String,)
     })
 }
 
-pub async fn login(service: web::Data<AuthService>, body: web::Json<LoginRequest>) -> actix_web::Result<HttpResponse> {
+pub async fn login(service: web::Data<AuthService>, body: ValidatedJson<LoginRequest>) -> actix_web::Result<HttpResponse> {
     let login_body = body.into_inner();
     info!("login request: {}", login_body.email);
 
@@ -22,7 +23,7 @@ pub async fn login(service: web::Data<AuthService>, body: web::Json<LoginRequest
     })
 }
 
-pub async fn register(service: web::Data<AuthService>, body: web::Json<RegisterRequest>) -> actix_web::Result<HttpResponse> {
+pub async fn register(service: web::Data<AuthService>, body: ValidatedJson<RegisterRequest>) -> actix_web::Result<HttpResponse> {
     let login_body = body.into_inner();
     info!("register request: {}", login_body.email);
 
@@ -0,0 +1,221 @@
+//! gRPC-Web calls of browser clients forwarded to the backends as gRPC
+
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::stream;
+use http_body_util::{BodyExt, Full};
+use log::{info, warn};
+use std::convert::Infallible;
+use tonic::codegen::http;
+use tonic::Status;
+use crate::error::api_error;
+use crate::services::transcoding_service::{BackendRpc, TranscodingService};
+
+const GRPC_WEB: &str = "application/grpc-web";
+
+/// Base64 encoded bodies for clients which can not read binary responses
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+// Flag of the frame which carries the trailers at the end of a gRPC-Web response body
+const TRAILER_FLAG: u8 = 0x80;
+
+// Sent to the backend besides custom `x-` headers, the request id is added by the channel
+const FORWARDED_HEADERS: [&str; 4] = ["authorization", "grpc-timeout", "grpc-encoding", "grpc-accept-encoding"];
+
+// Set by browsers and gRPC-Web clients for the gateway, not for the backend
+const BROWSER_HEADERS: [&str; 4] = ["x-grpc-web", "x-user-agent", "x-forwarded-for", "x-request-id"];
+
+// Describe the gRPC response itself, the gRPC-Web response has its own
+const RESPONSE_HEADERS: [&str; 4] = ["content-type", "content-length", "te", "trailer"];
+
+// Moved into the trailer frame when the backend answers without a body
+const STATUS_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+/// Forwards the gRPC frames of the body to the backend and streams the answer back, the trailers of the backend
+/// become the last frame of the body, as browsers can not read HTTP trailers.
+pub async fn grpc_web(req: HttpRequest, body: web::Bytes, rpc: web::Data<BackendRpc>, service: web::Data<TranscodingService>) -> actix_web::Result<HttpResponse> {
+    let content_type = req.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let text = content_type.starts_with(GRPC_WEB_TEXT);
+    if !text && !content_type.starts_with(GRPC_WEB) {
+        return Err(api_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("content type must be {} or {}", GRPC_WEB, GRPC_WEB_TEXT)));
+    }
+    info!("grpc_web request {} to {} backend, text: {}", rpc.rpc.full_name(), rpc.backend, text);
+
+    let frames = match text {
+        true => decode_text(&body).map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid base64 body: {}", e)))?,
+        false => body.to_vec(),
+    };
+
+    // `application/grpc-web+proto` becomes `application/grpc+proto`
+    let grpc_content_type = content_type.replacen(if text { GRPC_WEB_TEXT } else { GRPC_WEB }, "application/grpc", 1);
+    let mut request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(format!("/{}/{}", rpc.rpc.parent_service().full_name(), rpc.rpc.name()))
+        .header(http::header::CONTENT_TYPE, grpc_content_type)
+        .header(http::header::TE, "trailers");
+    for (name, value) in req.headers() {
+        let name = name.as_str();
+        if FORWARDED_HEADERS.contains(&name) || (name.starts_with("x-") && !BROWSER_HEADERS.contains(&name)) {
+            request = request.header(name, value.as_bytes());
+        }
+    }
+    let request = request.body(tonic::body::boxed(Full::new(web::Bytes::from(frames))))
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let mut response = HttpResponse::Ok();
+    response.content_type(content_type);
+
+    let upstream = match service.forward(&rpc.backend, request).await {
+        Ok(upstream) if upstream.status().is_success() => upstream,
+        Ok(upstream) => return Ok(trailers_only(response, &Status::unknown(format!("backend answered {}", upstream.status())), text)),
+        Err(status) => return Ok(trailers_only(response, &status, text)),
+    };
+    let (parts, body) = upstream.into_parts();
+
+    // A trailers-only answer of the backend carries the status in its headers
+    let mut header_trailers = http::HeaderMap::new();
+    for (name, value) in &parts.headers {
+        match name.as_str() {
+            header if RESPONSE_HEADERS.contains(&header) => {},
+            header if STATUS_HEADERS.contains(&header) => {
+                header_trailers.insert(name.clone(), value.clone());
+            },
+            header => {
+                response.insert_header((header, value.as_bytes()));
+            },
+        }
+    }
+
+    let frames = stream::unfold(Some((body, header_trailers)), move |state| async move {
+        let (mut body, header_trailers) = state?;
+        loop {
+            let frame = match body.frame().await {
+                Some(Ok(frame)) => frame,
+                Some(Err(status)) => {
+                    warn!("grpc_web response of backend failed, {}", status);
+                    return Some((Ok(encode(trailer_frame(&status_trailers(&status)), text)), None));
+                },
+                None => return Some((Ok(encode(trailer_frame(&header_trailers), text)), None)),
+            };
+            match frame.into_data() {
+                Ok(data) => return Some((Ok::<_, Infallible>(encode(data, text)), Some((body, header_trailers)))),
+                Err(frame) => if let Ok(trailers) = frame.into_trailers() {
+                    return Some((Ok(encode(trailer_frame(&trailers), text)), None));
+                },
+            }
+        }
+    });
+
+    Ok(response.streaming(frames))
+}
+
+// Answers without a message, only with the trailer frame carrying the status
+fn trailers_only(mut response: actix_web::HttpResponseBuilder, status: &Status, text: bool) -> HttpResponse {
+    warn!("grpc_web call failed, {}", status);
+    response.body(encode(trailer_frame(&status_trailers(status)), text))
+}
+
+fn status_trailers(status: &Status) -> http::HeaderMap {
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", http::HeaderValue::from(status.code() as i32));
+    // Line breaks would end the trailer, the message is for humans only
+    let message = status.message().replace(['\r', '\n'], " ");
+    if let Ok(message) = http::HeaderValue::from_str(&message) {
+        trailers.insert("grpc-message", message);
+    }
+    trailers
+}
+
+fn trailer_frame(trailers: &http::HeaderMap) -> web::Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = Vec::with_capacity(block.len() + 5);
+    frame.push(TRAILER_FLAG);
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&block);
+    web::Bytes::from(frame)
+}
+
+// Every chunk is padded on its own, clients decode the body in groups of four characters
+fn encode(data: web::Bytes, text: bool) -> web::Bytes {
+    match text {
+        true => web::Bytes::from(STANDARD.encode(&data)),
+        false => data,
+    }
+}
+
+fn decode_text(body: &[u8]) -> Result<Vec<u8>, base64::DecodeError> {
+    let body = body.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect::<Vec<_>>();
+
+    let mut frames = Vec::with_capacity(body.len() / 4 * 3);
+    for group in body.chunks(4) {
+        frames.extend(STANDARD.decode(group)?);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn text_bodies_decode_separately_padded_chunks() {
+        assert_eq!(decode_text(b"YWI=Yw==").unwrap(), b"abc");
+        assert_eq!(decode_text(b"YWJj").unwrap(), b"abc");
+    }
+
+    #[test]
+    fn text_bodies_ignore_line_breaks() {
+        assert_eq!(decode_text(b"YWI=\r\nYw==\n").unwrap(), b"abc");
+    }
+
+    #[test]
+    fn invalid_text_bodies_are_rejected() {
+        assert!(decode_text(b"YW!=").is_err());
+    }
+
+    #[test]
+    fn text_chunks_are_padded_on_their_own() {
+        let data = encode(web::Bytes::from_static(b"ab"), true);
+        let trailers = encode(web::Bytes::from_static(b"c"), true);
+
+        assert_eq!(data, "YWI=");
+        assert_eq!(trailers, "Yw==");
+        assert_eq!(encode(web::Bytes::from_static(b"ab"), false), "ab");
+    }
+
+    #[test]
+    fn trailer_frames_have_the_flag_and_length_prefix() {
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", http::HeaderValue::from(0));
+
+        let frame = trailer_frame(&trailers);
+
+        assert_eq!(frame[0], TRAILER_FLAG);
+        assert_eq!(frame[1..5], 16u32.to_be_bytes());
+        assert_eq!(&frame[5..], b"grpc-status: 0\r\n");
+    }
+
+    #[test]
+    fn status_messages_stay_on_one_line() {
+        let trailers = status_trailers(&Status::new(Code::NotFound, "order\r\nnot found"));
+
+        assert_eq!(trailers["grpc-status"], "5");
+        assert_eq!(trailers["grpc-message"], "order  not found");
+    }
+}
@@ -1,38 +1,148 @@
-use actix_web::{web, HttpResponse};
-use log::info;
-use crate::models::order_models::OrderRequest;
-use crate::routes::handle_result;
+use std::collections::{BTreeMap, HashMap};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::join_all;
+use log::{info, warn};
+use tonic::Code;
+use validator::ValidationErrors;
+use crate::error::{conflict_error, validation_error, Error};
+use crate::models::order_models::{validate_line_totals, ExpandedLineItem, ExpandedOrderResponse, LineItemProduct, OrderEntityResponse, OrderLineItems, OrderListQuery, OrderQuery, OrderRequest, PricedLineItem};
+use crate::routes::{error_response, handle_page, handle_result};
+use crate::validation::{field_errors, ValidatedJson, ValidatedQuery};
 use crate::services::order_service::OrderService;
+use crate::services::product_service::ProductService;
 use itertools::Itertools;
 
-pub async fn place_order(service: web::Data<OrderService>, body: web::Json<OrderRequest>) -> actix_web::Result<HttpResponse> {
+pub async fn place_order(service: web::Data<OrderService>, products: web::Data<ProductService>, body: ValidatedJson<OrderRequest>) -> actix_web::Result<HttpResponse> {
     let request = body.into_inner();
     let sku_codes = request.items.iter()
         .map(|x| &x.sku_code).join(",");
     info!("save product request, sku_codes {}", sku_codes);
 
-    handle_result(service.place_order(request).await, |order_number| {
+    let items = price_items(request.items, &products).await?;
+
+    // Catalogue prices filled in for items without a price can still mix currencies
+    validate_line_totals(items.iter().map(|item| (&item.price, item.quantity))).map_err(|e| {
+        let mut errors = ValidationErrors::new();
+        errors.add("items", e);
+        validation_error("invalid request body", field_errors(&errors))
+    })?;
+
+    handle_result(service.place_order(items).await, |order_number| {
         info!("save order success, order_number: {}", order_number);
     })
 }
 
-pub async fn get_order_list(service: web::Data<OrderService>) -> actix_web::Result<HttpResponse> {
-    info!("get_list_orders request");
+/// Checks every item against the catalogue, items without a price get the catalogue price.
+/// Unknown SKUs and prices which differ from the catalogue are answered with 409 listing the offending SKUs.
+async fn price_items(items: Vec<OrderLineItems>, service: &ProductService) -> actix_web::Result<Vec<PricedLineItem>> {
+    let sku_codes: Vec<&String> = items.iter().map(|item| &item.sku_code).unique().collect();
+    let lookups = join_all(sku_codes.iter().map(|sku_code| service.get_product((*sku_code).clone()))).await;
+
+    let mut catalogue = HashMap::new();
+    for (sku_code, lookup) in sku_codes.into_iter().zip(lookups) {
+        match lookup {
+            Ok(product) => {
+                catalogue.insert(sku_code.clone(), product.price);
+            },
+            Err(Error::GrpcStatus { status, .. }) if status.code() == Code::NotFound => {},
+            Err(e) => return Err(error_response(e)),
+        }
+    }
+
+    let mut conflicts = BTreeMap::new();
+    let mut offending_skus = Vec::new();
+    let mut priced = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        match catalogue.get(&item.sku_code) {
+            None => {
+                conflicts.insert(format!("items[{}].sku_code", index), vec!["unknown product".to_owned()]);
+                offending_skus.push(item.sku_code);
+            },
+            Some(price) if item.price.as_ref().is_some_and(|p| p != price) => {
+                conflicts.insert(format!("items[{}].price", index), vec![format!("must match the catalogue price {} {}", price.amount, price.currency)]);
+                offending_skus.push(item.sku_code);
+            },
+            Some(price) => priced.push(PricedLineItem { sku_code: item.sku_code, price: price.clone(), quantity: item.quantity }),
+        }
+    }
+
+    if !offending_skus.is_empty() {
+        let offending_skus = offending_skus.into_iter().unique().join(", ");
+        warn!("order items do not match the catalogue, sku_codes {}", offending_skus);
+        return Err(conflict_error(format!("order items do not match the catalogue: {}", offending_skus), conflicts));
+    }
+
+    Ok(priced)
+}
+
+pub async fn get_order_list(req: HttpRequest, service: web::Data<OrderService>, query: ValidatedQuery<OrderListQuery>) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    info!("get_list_orders request, {:?}", query);
 
-    handle_result(service.get_order_list().await, |oer| {
-        info!("get order list return {} orders", oer.len());
+    handle_page(&req, service.get_order_list(query).await, |oer| {
+        info!("get order list return {} orders, next_cursor: {:?}", oer.items.len(), oer.next_cursor);
     })
 }
 
-pub async fn delete_order(service: web::Data<OrderService>, id: web::Path<i64>) -> actix_web::Result<HttpResponse> {
+pub async fn get_order(service: web::Data<OrderService>, products: web::Data<ProductService>, id: web::Path<i64>, query: ValidatedQuery<OrderQuery>) -> actix_web::Result<HttpResponse> {
     let order_id = id.into_inner();
-    info!("delete_order request order_id = {}", order_id);
+    let query = query.into_inner();
+    info!("get_order request order_id = {}, expand: {:?}", order_id, query.expand);
+
+    if !query.expands("products") {
+        return handle_result(service.get_order(order_id).await, |order| {
+            info!("get_order response order_id = {}, status: {:?}", order.order_id, order.status);
+        });
+    }
 
-    handle_result(service.delete_order(order_id).await, |oer| {
-        let mut deleted_msg = "is deleted";
-        if !oer {
-           deleted_msg = "is not deleted"
+    handle_result(expand_products(service.get_order(order_id).await, &products).await, |order| {
+        info!("get_order response order_id = {}, status: {:?}, unavailable_products: {:?}", order.order_id, order.status, order.unavailable_products);
+    })
+}
+
+/// Looks up the distinct SKUs of the order concurrently, a failed lookup leaves its items without `product`
+/// instead of failing the order.
+async fn expand_products(order: Result<OrderEntityResponse, Error>, service: &ProductService) -> Result<ExpandedOrderResponse, Error> {
+    let order = order?;
+
+    let sku_codes: Vec<&String> = order.items.iter().map(|item| &item.sku_code).unique().collect();
+    let lookups = join_all(sku_codes.iter().map(|sku_code| service.get_product((*sku_code).clone()))).await;
+
+    let mut products = HashMap::new();
+    let mut unavailable_products = Vec::new();
+    for (sku_code, lookup) in sku_codes.into_iter().zip(lookups) {
+        match lookup {
+            Ok(product) => {
+                products.insert(sku_code.clone(), LineItemProduct { name: product.name, description: product.description, currency: product.price.currency });
+            },
+            Err(e) => {
+                warn!("product {} of order_id = {} is unavailable, {}", sku_code, order.order_id, e);
+                unavailable_products.push(sku_code.clone());
+            }
         }
-        info!("order_id = {} {}", order_id, deleted_msg);
+    }
+
+    let items = order.items.into_iter().map(|item| {
+        let product = products.get(&item.sku_code).cloned();
+        ExpandedLineItem { sku_code: item.sku_code, price: item.price, quantity: item.quantity, line_total: item.line_total, product }
+    }).collect();
+
+    Ok(ExpandedOrderResponse {
+        order_id: order.order_id,
+        order_number: order.order_number,
+        created_at: order.created_at,
+        items,
+        status: order.status,
+        totals: order.totals,
+        unavailable_products,
+    })
+}
+
+pub async fn cancel_order(service: web::Data<OrderService>, id: web::Path<i64>) -> actix_web::Result<HttpResponse> {
+    let order_id = id.into_inner();
+    info!("cancel_order request order_id = {}", order_id);
+
+    handle_result(service.cancel_order(order_id).await, |order| {
+        info!("order_id = {} is {:?}", order.order_id, order.status);
     })
 }
\ No newline at end of file
@@ -1,10 +1,17 @@
-use actix_web::{web, HttpResponse};
+use actix_web::http::header::{EntityTag, ETag, Header, IfMatch};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
 use log::info;
-use crate::models::product_models::ProductRequest;
-use crate::routes::handle_result;
+use serde_json::{Map, Value};
+use validator::Validate;
+use crate::error::{api_error, validation_error};
+use crate::middleware::compression::without_coding;
+use crate::models::product_models::{ProductListQuery, ProductRequest, ProductResponse};
+use crate::routes::{error_response, handle_page, handle_result};
+use crate::validation::{field_errors, ValidatedJson, ValidatedQuery};
 use crate::services::product_service::ProductService;
 
-pub async fn save_product(service: web::Data<ProductService>, body: web::Json<ProductRequest>) -> actix_web::Result<HttpResponse> {
+pub async fn save_product(service: web::Data<ProductService>, body: ValidatedJson<ProductRequest>) -> actix_web::Result<HttpResponse> {
     let request = body.into_inner();
     info!("save product request, name: {}, description: {}", request.name, request.description);
 
@@ -13,11 +20,12 @@ pub async fn save_product(service: web::Data<ProductService>, body: web::Json<Pr
     })
 }
 
-pub async fn get_list_products(service: web::Data<ProductService>) -> actix_web::Result<HttpResponse> {
-    info!("get_list_products request");
+pub async fn get_list_products(req: HttpRequest, service: web::Data<ProductService>, query: ValidatedQuery<ProductListQuery>) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    info!("get_list_products request, {:?}", query);
 
-    handle_result(service.get_product_list().await, |products| {
-        info!("get_list_products response, products: {}", products.len());
+    handle_page(&req, service.get_product_list(query).await, |products| {
+        info!("get_list_products response, products: {}, next_cursor: {:?}", products.items.len(), products.next_cursor);
     })
 }
 
@@ -32,4 +40,97 @@ pub async fn delete_product(service: web::Data<ProductService>, id: web::Path<St
         }
         info!("product_id = {} {}", product_id, deleted_msg);
     })
+}
+
+pub async fn get_product(service: web::Data<ProductService>, id: web::Path<String>) -> actix_web::Result<HttpResponse> {
+    let product_id = id.into_inner();
+    info!("get_product request {}", product_id);
+
+    let product = service.get_product(product_id).await.map_err(error_response)?;
+    info!("get_product response, product.id: {}, version: {}", product.id, product.version);
+
+    Ok(versioned(product))
+}
+
+pub async fn update_product(req: HttpRequest, service: web::Data<ProductService>, id: web::Path<String>, body: ValidatedJson<ProductRequest>) -> actix_web::Result<HttpResponse> {
+    let product_id = id.into_inner();
+    let version = if_match_version(&req)?;
+    info!("update_product request {}, if_match: {:?}", product_id, version);
+
+    let product = service.update_product(product_id, body.into_inner(), version.unwrap_or_default()).await
+        .map_err(error_response)?;
+    info!("update_product success, product.id: {}, version: {}", product.id, product.version);
+
+    Ok(versioned(product))
+}
+
+/// Applies a JSON merge patch (RFC 7396) to the current product and stores the result.
+pub async fn patch_product(req: HttpRequest, service: web::Data<ProductService>, id: web::Path<String>, patch: web::Json<Value>) -> actix_web::Result<HttpResponse> {
+    let product_id = id.into_inner();
+    let version = if_match_version(&req)?;
+    info!("patch_product request {}, if_match: {:?}", product_id, version);
+
+    let current = service.get_product(product_id.clone()).await.map_err(error_response)?;
+    if version.is_some_and(|v| v != current.version) {
+        return Err(api_error(StatusCode::PRECONDITION_FAILED, format!("product {} has version {}", product_id, current.version)));
+    }
+
+    let mut document = serde_json::to_value(ProductRequest {
+        name: current.name,
+        description: current.description,
+        price: current.price,
+    })?;
+    merge_patch(&mut document, &patch.into_inner());
+
+    let request: ProductRequest = serde_json::from_value(document)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid patch: {}", e)))?;
+    request.validate()
+        .map_err(|e| validation_error("invalid request body", field_errors(&e)))?;
+
+    // Without If-Match the patch still must not overwrite a change made since the product was read
+    let product = service.update_product(product_id, request, current.version).await
+        .map_err(error_response)?;
+    info!("patch_product success, product.id: {}, version: {}", product.id, product.version);
+
+    Ok(versioned(product))
+}
+
+fn versioned(product: ProductResponse) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ETag(EntityTag::new_strong(product.version.to_string())))
+        .json(product)
+}
+
+// Only strong tags carrying a product version are accepted, `*` matches any version
+fn if_match_version(req: &HttpRequest) -> actix_web::Result<Option<i64>> {
+    let invalid = || api_error(StatusCode::BAD_REQUEST, "If-Match must be a product version, e.g. \"3\"");
+
+    match IfMatch::parse(req).map_err(|_| invalid())? {
+        IfMatch::Any => Ok(None),
+        IfMatch::Items(tags) => match tags.as_slice() {
+            [] => Ok(None),
+            // Tags of compressed bodies carry the encoding, e.g. `"3-gzip"`
+            [tag] if !tag.weak => without_coding(tag).as_ref().unwrap_or(tag).tag().parse().map(Some).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
 }
\ No newline at end of file
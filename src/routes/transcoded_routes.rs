@@ -0,0 +1,170 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::info;
+use prost_reflect::{DynamicMessage, Kind};
+use serde_json::{Map, Value};
+use crate::error::api_error;
+use crate::routes::error_response;
+use crate::services::transcoding_service::{field_path, HttpBinding, TranscodingService};
+
+/// Serves one `google.api.http` binding, path variables, query parameters and the body make up the request message.
+pub async fn transcode(req: HttpRequest, body: web::Bytes, binding: web::Data<HttpBinding>, service: web::Data<TranscodingService>) -> actix_web::Result<HttpResponse> {
+    info!("transcode request {} {} to {}", req.method(), req.path(), binding.rpc.full_name());
+
+    let request = request_message(&req, &body, &binding)?;
+    let response = service.call(&binding.backend, &binding.rpc, request).await.map_err(error_response)?;
+
+    let mut json = serde_json::to_value(&response)?;
+    if let Some(field) = &binding.response_body {
+        let name = field_path(&binding.rpc.output(), field).and_then(|(names, _, _)| names.into_iter().next());
+        json = name.and_then(|name| json.get(&name).cloned()).unwrap_or(Value::Null);
+    }
+    info!("transcode response of {}", binding.rpc.full_name());
+
+    Ok(HttpResponse::Ok().json(json))
+}
+
+/// Calls a unary RPC of a dynamic backend with the proto3 JSON of its request message as body.
+pub async fn call_rpc(path: web::Path<(String, String, String)>, body: web::Bytes, service: web::Data<TranscodingService>) -> actix_web::Result<HttpResponse> {
+    let (backend, service_name, method) = path.into_inner();
+    info!("call_rpc request {}/{} of {} backend", service_name, method, backend);
+
+    let rpc = service.dynamic_rpc(&backend, &service_name, &method)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("{} backend has no unary rpc {}/{}", backend, service_name, method)))?;
+
+    let json = if body.is_empty() {
+        Value::Object(Map::new())
+    } else {
+        serde_json::from_slice(&body).map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid request body: {}", e)))?
+    };
+    let request = DynamicMessage::deserialize(rpc.input(), json)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("invalid request: {}", e)))?;
+
+    let response = service.call(&backend, &rpc, request).await.map_err(error_response)?;
+    info!("call_rpc response of {}", rpc.full_name());
+
+    Ok(HttpResponse::Ok().json(serde_json::to_value(&response)?))
+}
+
+// Builds the proto3 JSON of the request and lets the JSON mapping convert it, path variables win over the body
+fn request_message(req: &HttpRequest, body: &[u8], binding: &HttpBinding) -> actix_web::Result<DynamicMessage> {
+    let input = binding.rpc.input();
+    let invalid = |message: String| api_error(StatusCode::BAD_REQUEST, message);
+
+    let mut json = Value::Object(Map::new());
+    if let Some(field) = &binding.body {
+        let body = if body.is_empty() {
+            Value::Object(Map::new())
+        } else {
+            serde_json::from_slice(body).map_err(|e| invalid(format!("invalid request body: {}", e)))?
+        };
+        match field.as_str() {
+            "*" => json = body,
+            field => {
+                let (names, _, _) = field_path(&input, field).ok_or_else(|| invalid(format!("unknown body field {}", field)))?;
+                insert(&mut json, &names, body, false);
+            }
+        }
+    }
+
+    let query = serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+        .map_err(|e| invalid(format!("invalid query string: {}", e)))?;
+    for (name, value) in &query {
+        let (names, kind, repeated) = field_path(&input, name).ok_or_else(|| invalid(format!("unknown query parameter {}", name)))?;
+        insert(&mut json, &names, scalar(&kind, value).map_err(|e| invalid(format!("{} {}", name, e)))?, repeated);
+    }
+
+    for (variable, field) in &binding.path_fields {
+        let value = req.match_info().get(variable).unwrap_or_default();
+        if let Some((names, kind, repeated)) = field_path(&input, field) {
+            insert(&mut json, &names, scalar(&kind, value).map_err(|e| invalid(format!("{} {}", field, e)))?, repeated);
+        }
+    }
+
+    DynamicMessage::deserialize(input, json).map_err(|e| invalid(format!("invalid request: {}", e)))
+}
+
+// Path and query values are strings, which the JSON mapping accepts for numbers, enums, bytes and timestamps
+fn scalar(kind: &Kind, value: &str) -> Result<Value, String> {
+    let is_bool = match kind {
+        Kind::Bool => true,
+        Kind::Message(message) => message.full_name() == "google.protobuf.BoolValue",
+        _ => false,
+    };
+    if is_bool {
+        return value.parse().map(Value::Bool).map_err(|_| "must be true or false".to_owned());
+    }
+    Ok(Value::String(value.to_owned()))
+}
+
+fn insert(json: &mut Value, names: &[String], value: Value, repeated: bool) {
+    let Some((name, parents)) = names.split_last() else {
+        return;
+    };
+
+    let mut target = json;
+    for parent in parents {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        target = &mut target[parent.as_str()];
+    }
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    let slot = &mut target[name.as_str()];
+    match slot {
+        Value::Array(values) if repeated => values.push(value),
+        _ if repeated => *slot = Value::Array(vec![value]),
+        _ => *slot = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(path: &str) -> Vec<String> {
+        path.split('.').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn nested_query_fields_build_nested_objects() {
+        let mut json = json!({});
+
+        insert(&mut json, &names("createdAfter.seconds"), json!("1700000000"), false);
+        insert(&mut json, &names("createdAfter.nanos"), json!("5"), false);
+        insert(&mut json, &names("limit"), json!("10"), false);
+
+        assert_eq!(json, json!({"createdAfter": {"seconds": "1700000000", "nanos": "5"}, "limit": "10"}));
+    }
+
+    #[test]
+    fn repeated_query_fields_collect_every_value() {
+        let mut json = json!({});
+
+        insert(&mut json, &names("filter.skus"), json!("a"), true);
+        insert(&mut json, &names("filter.skus"), json!("b"), true);
+
+        assert_eq!(json, json!({"filter": {"skus": ["a", "b"]}}));
+    }
+
+    #[test]
+    fn later_values_replace_singular_fields() {
+        let mut json = json!({"limit": "10"});
+
+        insert(&mut json, &names("limit"), json!("20"), false);
+
+        assert_eq!(json, json!({"limit": "20"}));
+    }
+
+    #[test]
+    fn bools_are_parsed_and_everything_else_stays_a_string() {
+        assert_eq!(scalar(&Kind::Bool, "true"), Ok(Value::Bool(true)));
+        assert!(scalar(&Kind::Bool, "yes").is_err());
+        assert_eq!(scalar(&Kind::Int64, "42"), Ok(json!("42")));
+        assert_eq!(scalar(&Kind::String, "true"), Ok(json!("true")));
+    }
+}
@@ -1,3 +1,183 @@
+use crate::config::{watch_files, BackendTlsConfig, GrpcCompression};
+use crate::error::Error;
+use crate::middleware::request_id;
+use log::{error, info};
+use std::error::Error as StdError;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tonic::service::interceptor::InterceptedService;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status};
+
 pub mod auth_service;
 pub mod product_service;
-pub mod order_service;
\ No newline at end of file
+pub mod order_service;
+pub mod single_flight;
+pub mod reflection;
+pub mod transcoding_service;
+
+/// Channel used by all backend clients, every call goes through the request id interceptor.
+pub type GrpcChannel = InterceptedService<Channel, fn(Request<()>) -> Result<Request<()>, Status>>;
+
+/// Channel of one backend shared by all of its clients, replaced when the TLS files of the backend change.
+pub type SharedChannel = Arc<RwLock<GrpcChannel>>;
+
+/// Shared channel of a backend together with the encoding of the messages sent to it.
+#[derive(Debug, Clone)]
+pub struct BackendChannel {
+    channel: SharedChannel,
+    compression: Option<CompressionEncoding>,
+}
+
+impl BackendChannel {
+    pub fn new(backend: &BackendConfig, channel: SharedChannel) -> Self {
+        BackendChannel { channel, compression: backend.compression() }
+    }
+
+    /// Channel of the backend right now, it is replaced when the TLS files change, so clients are built per call.
+    pub fn current(&self) -> GrpcChannel {
+        self.channel.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Client on the current channel, compressed responses are always accepted.
+    pub fn client<C: CompressedClient>(&self, new: impl FnOnce(GrpcChannel) -> C) -> C {
+        let client = new(self.current())
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd);
+
+        match self.compression {
+            Some(encoding) => client.send_compressed(encoding),
+            None => client,
+        }
+    }
+}
+
+/// Generated client of a backend service, or the generic `Grpc` client, implemented through `compressed_client!`.
+pub trait CompressedClient: Sized {
+    fn accept_compressed(self, encoding: CompressionEncoding) -> Self;
+    fn send_compressed(self, encoding: CompressionEncoding) -> Self;
+}
+
+// The generated clients only have these as inherent methods
+macro_rules! compressed_client {
+    ($client:ty) => {
+        impl $crate::services::CompressedClient for $client {
+            fn accept_compressed(self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                <$client>::accept_compressed(self, encoding)
+            }
+
+            fn send_compressed(self, encoding: tonic::codec::CompressionEncoding) -> Self {
+                <$client>::send_compressed(self, encoding)
+            }
+        }
+    };
+}
+pub(crate) use compressed_client;
+
+/// Connection settings of one backend service.
+#[derive(Debug, Clone)]
+pub struct BackendConfig {
+    pub name: String,
+    pub endpoint: String,
+    pub tls: Option<BackendTlsConfig>,
+    pub compression: Option<GrpcCompression>,
+}
+
+impl BackendConfig {
+    fn endpoint(&self) -> Result<Endpoint, Error> {
+        let endpoint = Channel::from_shared(self.endpoint.clone())?;
+
+        let Some(tls) = &self.tls else {
+            return Ok(endpoint);
+        };
+
+        // tonic silently falls back to plaintext for http:// endpoints
+        if endpoint.uri().scheme_str() != Some("https") {
+            return Err(self.connect_error("TLS is configured but the endpoint is not https://".to_owned()));
+        }
+
+        // tonic is built without a root store, a handshake without the CA could never succeed
+        let Some(ca) = &tls.ca else {
+            return Err(self.connect_error("TLS requires ca, system roots are not trusted".to_owned()));
+        };
+        let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(fs::read(ca)?));
+        match (&tls.cert, &tls.key) {
+            (Some(cert), Some(key)) => {
+                tls_config = tls_config.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+            }
+            (None, None) => {}
+            _ => return Err(self.connect_error("both cert and key are required for a client certificate".to_owned())),
+        }
+        if let Some(server_name) = &tls.server_name {
+            tls_config = tls_config.domain_name(server_name.clone());
+        }
+
+        Ok(endpoint.tls_config(tls_config)?)
+    }
+
+    /// Connects eagerly, so an unreachable or untrusted backend fails the startup.
+    pub async fn connect(&self) -> Result<GrpcChannel, Error> {
+        let channel = self.endpoint()?.connect().await
+            .map_err(|e| self.connect_error(error_chain(&e)))?;
+
+        Ok(intercept(channel))
+    }
+
+    /// Connects like `connect` and keeps the channel up to date with the TLS files of the backend.
+    pub async fn connect_shared(&self, reload_interval: Duration) -> Result<SharedChannel, Error> {
+        let channel = Arc::new(RwLock::new(self.connect().await?));
+        self.watch_tls(Arc::clone(&channel), reload_interval);
+        Ok(channel)
+    }
+
+    /// Encoding of the messages sent to the backend, if any.
+    fn compression(&self) -> Option<CompressionEncoding> {
+        self.compression.map(|c| match c {
+            GrpcCompression::Gzip => CompressionEncoding::Gzip,
+            GrpcCompression::Zstd => CompressionEncoding::Zstd,
+        })
+    }
+
+    fn connect_error(&self, message: String) -> Error {
+        Error::BackendConnect { backend: self.name.clone(), endpoint: self.endpoint.clone(), message }
+    }
+
+    /// Replaces the channel with a lazily connected one whenever the TLS files of the backend change.
+    fn watch_tls(&self, channel: SharedChannel, interval: Duration) {
+        let Some(tls) = &self.tls else {
+            return;
+        };
+
+        let backend = self.clone();
+        watch_files(tls.paths(), interval, move || {
+            match backend.endpoint() {
+                Ok(endpoint) => {
+                    *channel.write().unwrap_or_else(|e| e.into_inner()) = intercept(endpoint.connect_lazy());
+                    info!("reloaded TLS settings of {} backend", backend.name);
+                }
+                Err(e) => error!("keeping previous TLS settings of {} backend, reload failed: {}", backend.name, e),
+            }
+        });
+    }
+}
+
+fn intercept(channel: Channel) -> GrpcChannel {
+    InterceptedService::new(channel, request_id::propagate as _)
+}
+
+// Transport errors hide the actual cause, e.g. an unknown certificate issuer, in their sources
+fn error_chain(e: &dyn StdError) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        let cause = e.to_string();
+        if !message.ends_with(&cause) {
+            message.push_str(": ");
+            message.push_str(&cause);
+        }
+        source = e.source();
+    }
+    message
+}
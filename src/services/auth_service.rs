@@ -1,5 +1,5 @@
-use tonic::transport::Channel;
 use crate::error::Error;
+use crate::services::{compressed_client, BackendChannel, BackendConfig, GrpcChannel, SharedChannel};
 use proto::auth_client::AuthClient;
 use crate::models::auth_models::{IsAdminResponse, LoginResponse, RegisterResponse};
 
@@ -7,18 +7,16 @@ mod proto {
     tonic::include_proto!("auth");
 }
 
+compressed_client!(AuthClient<GrpcChannel>);
+
 #[derive(Debug, Clone)]
 pub struct AuthService {
-    client: AuthClient<Channel>,
+    channel: BackendChannel,
 }
 
 impl AuthService {
-    pub async fn new(auth_endpoint: String) -> Result<Self, Error> {
-        let channel = Channel::from_shared(auth_endpoint)?.connect().await?;
-
-        let client = AuthClient::new(channel);
-
-        Ok(Self { client })
+    pub fn new(backend: &BackendConfig, channel: SharedChannel) -> Self {
+        Self { channel: BackendChannel::new(backend, channel) }
     }
 
     pub async fn is_admin(&self, user_id: &str) -> Result<IsAdminResponse, Error> {
@@ -26,7 +24,7 @@ impl AuthService {
             user_id: user_id.to_owned() 
         });
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(AuthClient::new);
 
         let response = client.is_admin(request).await
             .map_err(|s| Error::GrpcStatus { input: "is_admin failed".to_owned(), status: s })?;
@@ -42,7 +40,7 @@ impl AuthService {
             password: password.to_owned(),
         });
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(AuthClient::new);
 
         let response = client.register(request).await
             .map_err(|s| Error::GrpcStatus { input: "register failed".to_owned(), status: s })?;
@@ -59,7 +57,7 @@ impl AuthService {
             app_id: -1,
         });
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(AuthClient::new);
 
         let response = client.login(request).await
             .map_err(|s| Error::GrpcStatus { input: "login failed".to_owned(), status: s })?;
@@ -1,40 +1,51 @@
 use chrono::{DateTime, Utc};
+use log::warn;
 use prost_types::Timestamp;
-use tonic::transport::Channel;
 use crate::error::Error;
+use crate::middleware::metrics::MetricsPublisher;
+use crate::services::single_flight::SingleFlight;
+use crate::services::{compressed_client, BackendChannel, BackendConfig, GrpcChannel, SharedChannel};
+use std::sync::Arc;
 use proto::order_client::OrderClient;
-use crate::models::order_models::{OrderEntityResponse, OrderLineItems, OrderRequest};
+use crate::models::money_models::{totals, Money};
+use crate::models::order_models::{OrderEntityResponse, OrderLineItemResponse, OrderListQuery, OrderStatus, PricedLineItem};
+use crate::models::page_models::{Page, Sort, DEFAULT_PAGE_SIZE};
 
 mod proto {
     tonic::include_proto!("order");
 }
 
+compressed_client!(OrderClient<GrpcChannel>);
+
 #[derive(Debug, Clone)]
 pub struct OrderService {
-    client: OrderClient<Channel>
+    channel: BackendChannel,
+    list_calls: Arc<SingleFlight<proto::OrderListResponse>>,
+    get_calls: Arc<SingleFlight<proto::OrderEntityResponse>>,
 }
 
 impl OrderService {
-    pub async fn new(order_endpoint: String) -> Result<Self, Error> {
-        let channel = Channel::from_shared(order_endpoint)?.connect().await?;
-
-        let client = OrderClient::new(channel);
-
-        Ok(Self { client })
+    pub fn new(backend: &BackendConfig, channel: SharedChannel, metrics: Arc<MetricsPublisher>) -> Self {
+        Self {
+            channel: BackendChannel::new(backend, channel),
+            list_calls: Arc::new(SingleFlight::new(&backend.name, "get_order_list", Arc::clone(&metrics))),
+            get_calls: Arc::new(SingleFlight::new(&backend.name, "get_order", metrics)),
+        }
     }
 
-    pub async fn place_order(&self, order_request: OrderRequest) -> Result<String, Error> {
-        let items: Vec<proto::OrderLineItems> = order_request.items.into_iter().map(|item| proto::OrderLineItems {
+    pub async fn place_order(&self, items: Vec<PricedLineItem>) -> Result<String, Error> {
+        let items: Vec<proto::OrderLineItems> = items.into_iter().map(|item| proto::OrderLineItems {
             sku_code: item.sku_code,
-            price: item.price,
+            price: item.price.amount,
             quantity: item.quantity,
+            currency: item.price.currency,
         }).collect();
         let request = tonic::Request::new(
             proto::OrderRequest {
                 items,
             }
         );
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(OrderClient::new);
 
         let response = client.place(request).await
             .map_err(|s| Error::GrpcStatus { input: "save order failed".to_owned(), status: s})?;
@@ -44,42 +55,99 @@ impl OrderService {
         Ok(order_response.order_number)
     }
 
-    pub async fn get_order_list(&self) -> Result<Vec<OrderEntityResponse>, Error> {
-        let request = tonic::Request::new(proto::Empty {});
-        let mut client = self.client.clone();
-
-        let response = client.get_order_list(request).await
+    pub async fn get_order_list(&self, query: OrderListQuery) -> Result<Page<OrderEntityResponse>, Error> {
+        let sort = Sort::parse(query.sort.as_deref());
+        let request = proto::OrderListRequest {
+            limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE) as i32,
+            cursor: query.cursor.unwrap_or_default(),
+            offset: query.offset.unwrap_or_default() as i32,
+            sort_by: sort.field,
+            descending: sort.descending,
+            created_after: query.created_after.map(OrderService::datetime_to_timestamp),
+            created_before: query.created_before.map(OrderService::datetime_to_timestamp),
+        };
+        let mut client = self.channel.client(OrderClient::new);
+
+        let order_list = self.list_calls.call(request, |request| async move {
+            client.get_order_list(tonic::Request::new(request)).await.map(tonic::Response::into_inner)
+        }).await
             .map_err(|s| Error::GrpcStatus { input: "save order failed".to_owned(), status: s})?;
 
-        let oer: Vec<OrderEntityResponse> = response.into_inner().orders.into_iter().map(|o| {
-            OrderEntityResponse {
-                order_id: o.order_id,
-                order_number: o.order_number,
-                created_at: o.created_at.and_then(OrderService::timestamp_to_datetime),
-                items: o.items.into_iter().map(|item| {
-                    OrderLineItems {
-                        sku_code: item.sku_code,
-                        quantity: item.quantity,
-                        price: item.price,
-                    }
-                }).collect()
-            }
-        }).collect();
+        let oer = order_list.orders.into_iter().map(OrderService::map_to_order).collect();
 
-        Ok(oer)
+        Ok(Page { items: oer, next_cursor: Some(order_list.next_cursor).filter(|c| !c.is_empty()) })
     }
 
-    pub async fn delete_order(&self, order_id: i64) -> Result<bool, Error> {
-        let request = tonic::Request::new(proto::DeleteOrderRequest { order_id });
+    pub async fn get_order(&self, order_id: i64) -> Result<OrderEntityResponse, Error> {
+        let request = proto::GetOrderRequest { order_id };
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(OrderClient::new);
 
-        let response = client.delete_order(request).await
-            .map_err(|s| Error::GrpcStatus { input: format!("delete order with order_id = {} failed", order_id), status: s})?;
+        let order = self.get_calls.call(request, |request| async move {
+            client.get_order(tonic::Request::new(request)).await.map(tonic::Response::into_inner)
+        }).await
+            .map_err(|s| Error::GrpcStatus { input: format!("get order with order_id = {} failed", order_id), status: s})?;
+
+        Ok(OrderService::map_to_order(order))
+    }
 
-        let is_deleted = response.into_inner().is_deleted;
+    /// Cancels the order, the backend rejects orders which are already shipped or cancelled.
+    pub async fn cancel_order(&self, order_id: i64) -> Result<OrderEntityResponse, Error> {
+        let request = tonic::Request::new(proto::CancelOrderRequest { order_id });
+
+        let mut client = self.channel.client(OrderClient::new);
+
+        let response = client.cancel_order(request).await
+            .map_err(|s| Error::GrpcStatus { input: format!("cancel order with order_id = {} failed", order_id), status: s})?;
+
+        Ok(OrderService::map_to_order(response.into_inner()))
+    }
+
+    // Stored orders are shown even when their amounts overflow, only new orders are rejected for it
+    fn map_to_order(o: proto::OrderEntityResponse) -> OrderEntityResponse {
+        let status = match o.status() {
+            proto::OrderStatus::Unspecified => OrderStatus::Unknown,
+            proto::OrderStatus::Pending => OrderStatus::Pending,
+            proto::OrderStatus::Paid => OrderStatus::Paid,
+            proto::OrderStatus::Shipped => OrderStatus::Shipped,
+            proto::OrderStatus::Cancelled => OrderStatus::Cancelled,
+        };
+
+        let items = o.items.into_iter().map(|item| {
+            let price = Money::new(item.price, item.currency);
+            OrderLineItemResponse {
+                sku_code: item.sku_code,
+                quantity: item.quantity,
+                line_total: price.checked_mul(item.quantity).ok(),
+                price,
+            }
+        }).collect::<Vec<_>>();
+
+        let line_totals = items.iter().map(|item| item.line_total.as_ref()).collect::<Option<Vec<_>>>();
+        let order_totals = match line_totals.map(totals) {
+            Some(Ok(order_totals)) => Some(order_totals),
+            Some(Err(e)) => {
+                warn!("order {} has no totals, {}", o.order_number, e);
+                None
+            },
+            None => {
+                warn!("order {} has no totals, a line total overflows", o.order_number);
+                None
+            },
+        };
+
+        OrderEntityResponse {
+            order_id: o.order_id,
+            order_number: o.order_number,
+            created_at: o.created_at.and_then(OrderService::timestamp_to_datetime),
+            totals: order_totals,
+            items,
+            status,
+        }
+    }
 
-        Ok(is_deleted)
+    fn datetime_to_timestamp(dt: DateTime<Utc>) -> Timestamp {
+        Timestamp { seconds: dt.timestamp(), nanos: dt.timestamp_subsec_nanos() as i32 }
     }
 
     fn timestamp_to_datetime(ts: Timestamp) -> Option<DateTime<Utc>> {
@@ -1,35 +1,44 @@
 use crate::error::Error;
-use crate::models::product_models::{ProductRequest, ProductResponse};
+use crate::middleware::metrics::MetricsPublisher;
+use crate::services::single_flight::SingleFlight;
+use crate::services::{compressed_client, BackendChannel, BackendConfig, GrpcChannel, SharedChannel};
+use std::sync::Arc;
+use crate::models::money_models::Money;
+use crate::models::page_models::{Page, Sort, DEFAULT_PAGE_SIZE};
+use crate::models::product_models::{ProductListQuery, ProductRequest, ProductResponse};
 use proto::product_client::ProductClient;
-use tonic::transport::Channel;
 
 mod proto {
     tonic::include_proto!("product");
 }
 
+compressed_client!(ProductClient<GrpcChannel>);
+
 #[derive(Debug, Clone)]
 pub struct ProductService {
-    client: ProductClient<Channel>,
+    channel: BackendChannel,
+    list_calls: Arc<SingleFlight<proto::ProductListResponse>>,
+    get_calls: Arc<SingleFlight<proto::ProductResponse>>,
 }
 
 impl ProductService {
-    pub async fn new(product_endpoint: String) -> Result<Self, Error> {
-        let channel = Channel::from_shared(product_endpoint)?.connect().await?;
-
-        let client = ProductClient::new(channel);
-
-        Ok(Self { client })
+    pub fn new(backend: &BackendConfig, channel: SharedChannel, metrics: Arc<MetricsPublisher>) -> Self {
+        Self {
+            channel: BackendChannel::new(backend, channel),
+            list_calls: Arc::new(SingleFlight::new(&backend.name, "get_product_list", Arc::clone(&metrics))),
+            get_calls: Arc::new(SingleFlight::new(&backend.name, "get_product", metrics)),
+        }
     }
 
     pub async fn save_product(&self, product_request: ProductRequest) -> Result<ProductResponse, Error> {
         let request = tonic::Request::new(proto::ProductRequest {
             name: product_request.name,
             description: product_request.description,
-            currency: product_request.currency,
-            price: product_request.price,
+            currency: product_request.price.currency,
+            price: product_request.price.amount,
         });
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(ProductClient::new);
 
         let response = client.save(request).await
             .map_err(|s| Error::GrpcStatus { input: "save product failed".to_owned(), status: s})?;
@@ -41,25 +50,38 @@ impl ProductService {
         Ok(product_response)
     }
 
-    pub async fn get_product_list(&self) -> Result<Vec<ProductResponse>, Error> {
-        let request = tonic::Request::new(proto::Empty {});
-
-        let mut client = self.client.clone();
-
-        let response = client.get_product_list(request).await
+    pub async fn get_product_list(&self, query: ProductListQuery) -> Result<Page<ProductResponse>, Error> {
+        let sort = Sort::parse(query.sort.as_deref());
+        let request = proto::ProductListRequest {
+            limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE) as i32,
+            cursor: query.cursor.unwrap_or_default(),
+            offset: query.offset.unwrap_or_default() as i32,
+            sort_by: sort.field,
+            descending: sort.descending,
+            min_price: query.min_price,
+            max_price: query.max_price,
+            currency: query.currency.unwrap_or_default(),
+        };
+
+        let mut client = self.channel.client(ProductClient::new);
+
+        let product_list = self.list_calls.call(request, |request| async move {
+            client.get_product_list(tonic::Request::new(request)).await.map(tonic::Response::into_inner)
+        }).await
             .map_err(|s| Error::GrpcStatus { input: "get_product_by_id failed".to_owned(), status: s})?;
 
-        let product_list = response.into_inner();
-
-        let response = product_list.products.into_iter().map(ProductService::map_to_product).collect();
+        let page = Page {
+            items: product_list.products.into_iter().map(ProductService::map_to_product).collect(),
+            next_cursor: Some(product_list.next_cursor).filter(|c| !c.is_empty()),
+        };
 
-        Ok(response)
+        Ok(page)
     }
 
     pub async fn delete_product(&self, product_id: String) -> Result<bool, Error> {
         let request = tonic::Request::new(proto::DeleteProductRequest { id: product_id.clone() });
 
-        let mut client = self.client.clone();
+        let mut client = self.channel.client(ProductClient::new);
 
         let response = client.delete_product(request).await
             .map_err(|s| Error::GrpcStatus { input: format!("delete product with product_id = {} failed", product_id), status: s})?;
@@ -69,13 +91,45 @@ impl ProductService {
         Ok(is_deleted)
     }
 
+    pub async fn get_product(&self, product_id: String) -> Result<ProductResponse, Error> {
+        let request = proto::GetProductRequest { id: product_id.clone() };
+
+        let mut client = self.channel.client(ProductClient::new);
+
+        let product = self.get_calls.call(request, |request| async move {
+            client.get_product(tonic::Request::new(request)).await.map(tonic::Response::into_inner)
+        }).await
+            .map_err(|s| Error::GrpcStatus { input: format!("get product with product_id = {} failed", product_id), status: s})?;
+
+        Ok(ProductService::map_to_product(product))
+    }
+
+    /// Replaces the product, `version` 0 skips the optimistic concurrency check of the backend.
+    pub async fn update_product(&self, product_id: String, product_request: ProductRequest, version: i64) -> Result<ProductResponse, Error> {
+        let request = tonic::Request::new(proto::UpdateProductRequest {
+            id: product_id.clone(),
+            name: product_request.name,
+            description: product_request.description,
+            currency: product_request.price.currency,
+            price: product_request.price.amount,
+            version,
+        });
+
+        let mut client = self.channel.client(ProductClient::new);
+
+        let response = client.update_product(request).await
+            .map_err(|s| Error::GrpcStatus { input: format!("update product with product_id = {} failed", product_id), status: s})?;
+
+        Ok(ProductService::map_to_product(response.into_inner()))
+    }
+
     fn map_to_product(product: proto::ProductResponse) -> ProductResponse {
         ProductResponse {
             id: product.id,
             name: product.name,
             description: product.description,
-            currency: product.current,
-            price: product.price,
+            price: Money::new(product.price, product.current),
+            version: product.version,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+//! Descriptors of a backend's services fetched through gRPC server reflection (`grpc.reflection.v1`)
+
+use crate::error::Error;
+use crate::services::GrpcChannel;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use prost::Message;
+use prost_reflect::DescriptorPool;
+use prost_types::FileDescriptorProto;
+use proto::server_reflection_client::ServerReflectionClient;
+use proto::server_reflection_request::MessageRequest;
+use proto::server_reflection_response::MessageResponse;
+use proto::{ServerReflectionRequest, ServerReflectionResponse};
+use std::collections::HashMap;
+use tonic::Streaming;
+
+mod proto {
+    #![allow(clippy::enum_variant_names)]
+    tonic::include_proto!("grpc.reflection.v1");
+}
+
+/// Lists the services of the backend and loads the files declaring them together with their dependencies.
+pub async fn reflect_descriptors(backend: &str, channel: GrpcChannel) -> Result<DescriptorPool, Error> {
+    let input = || format!("server reflection of {} backend failed", backend);
+    let request = |message_request| ServerReflectionRequest { host: String::new(), message_request: Some(message_request) };
+
+    let (requests, receiver) = mpsc::unbounded();
+    let send = |message_request| requests.unbounded_send(request(message_request))
+        .map_err(|e| Error::Descriptor(format!("{}: {}", input(), e)));
+
+    send(MessageRequest::ListServices(String::new()))?;
+    let mut responses = ServerReflectionClient::new(channel).server_reflection_info(receiver).await
+        .map_err(|s| Error::GrpcStatus { input: input(), status: s })?
+        .into_inner();
+
+    let services = match next_response(&mut responses, &input).await? {
+        MessageResponse::ListServicesResponse(list) => list.service.into_iter().map(|s| s.name).collect::<Vec<_>>(),
+        _ => return Err(Error::Descriptor(format!("{}: unexpected answer to list_services", input()))),
+    };
+
+    let mut files = HashMap::new();
+    for service in services.into_iter().filter(|s| !s.starts_with("grpc.reflection.")) {
+        send(MessageRequest::FileContainingSymbol(service))?;
+        add_files(&mut files, next_response(&mut responses, &input).await?)?;
+    }
+
+    // Servers skip files already sent on the stream and may leave out dependencies, those are asked for by name
+    loop {
+        let missing = files.values()
+            .flat_map(|file: &FileDescriptorProto| file.dependency.iter())
+            .filter(|dependency| !files.contains_key(*dependency))
+            .cloned()
+            .collect::<Vec<_>>();
+        let Some(dependency) = missing.into_iter().next() else {
+            break;
+        };
+        send(MessageRequest::FileByFilename(dependency.clone()))?;
+        add_files(&mut files, next_response(&mut responses, &input).await?)?;
+        if !files.contains_key(&dependency) {
+            return Err(Error::Descriptor(format!("{}: missing file {}", input(), dependency)));
+        }
+    }
+    drop(requests);
+
+    let mut pool = DescriptorPool::new();
+    pool.add_file_descriptor_protos(dependency_order(files))
+        .map_err(|e| Error::Descriptor(format!("{}: {}", input(), e)))?;
+    Ok(pool)
+}
+
+async fn next_response(responses: &mut Streaming<ServerReflectionResponse>, input: &impl Fn() -> String) -> Result<MessageResponse, Error> {
+    let response = responses.next().await
+        .ok_or_else(|| Error::Descriptor(format!("{}: stream closed", input())))?
+        .map_err(|s| Error::GrpcStatus { input: input(), status: s })?;
+
+    match response.message_response {
+        Some(MessageResponse::ErrorResponse(e)) => Err(Error::Descriptor(format!("{}: {}", input(), e.error_message))),
+        Some(message_response) => Ok(message_response),
+        None => Err(Error::Descriptor(format!("{}: empty response", input()))),
+    }
+}
+
+fn add_files(files: &mut HashMap<String, FileDescriptorProto>, response: MessageResponse) -> Result<(), Error> {
+    let MessageResponse::FileDescriptorResponse(response) = response else {
+        return Err(Error::Descriptor("server reflection answered without files".to_owned()));
+    };
+    for bytes in response.file_descriptor_proto {
+        let file = FileDescriptorProto::decode(bytes.as_slice()).map_err(|e| Error::Descriptor(e.to_string()))?;
+        files.entry(file.name().to_owned()).or_insert(file);
+    }
+    Ok(())
+}
+
+// Every file comes after the files it imports, as the pool resolves imports while adding a file
+fn dependency_order(mut files: HashMap<String, FileDescriptorProto>) -> Vec<FileDescriptorProto> {
+    fn visit(name: &str, files: &mut HashMap<String, FileDescriptorProto>, ordered: &mut Vec<FileDescriptorProto>) {
+        let Some(file) = files.remove(name) else {
+            return;
+        };
+        for dependency in &file.dependency {
+            visit(dependency, files, ordered);
+        }
+        ordered.push(file);
+    }
+
+    let mut ordered = Vec::with_capacity(files.len());
+    let mut names = files.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        visit(&name, &mut files, &mut ordered);
+    }
+    ordered
+}
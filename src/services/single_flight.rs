@@ -0,0 +1,159 @@
+use crate::middleware::metrics::MetricsPublisher;
+use crate::middleware::request_id;
+use futures::future::{BoxFuture, FutureExt, Shared, WeakShared};
+use prost::Message;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tonic::Status;
+
+// Company of the caller and the encoded request message
+type CallKey = (String, Vec<u8>);
+
+type CallFuture<T> = BoxFuture<'static, Result<T, Status>>;
+
+// Only the waiters keep a call alive, the call is dropped once all of them went away
+type Calls<T> = Arc<Mutex<HashMap<CallKey, WeakShared<CallFuture<T>>>>>;
+
+/// Identical concurrent calls of one read RPC, the first caller makes the call and every other one awaits its result.
+pub struct SingleFlight<T> {
+    backend: String,
+    method: &'static str,
+    calls: Calls<T>,
+    publisher: Arc<MetricsPublisher>,
+}
+
+impl<T> SingleFlight<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    pub fn new(backend: &str, method: &'static str, publisher: Arc<MetricsPublisher>) -> Self {
+        SingleFlight { backend: backend.to_owned(), method, calls: Arc::new(Mutex::new(HashMap::new())), publisher }
+    }
+
+    /// Joins the call in flight for the same request and tenant, otherwise starts `call`.
+    pub async fn call<M, F, Fut>(&self, request: M, call: F) -> Result<T, Status>
+    where
+        M: Message,
+        F: FnOnce(M) -> Fut,
+        Fut: Future<Output = Result<T, Status>> + Send + 'static,
+    {
+        let tenant = request_id::with_context(|ctx| ctx.tenant()).flatten().unwrap_or_default();
+        let key = (tenant, request.encode_to_vec());
+
+        let (shared, result) = {
+            let mut calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+            match calls.get(&key).and_then(WeakShared::upgrade) {
+                Some(shared) => (shared, "deduplicated"),
+                None => {
+                    // Removed before the result is handed out, later callers never get a finished call
+                    let calls_ref = Arc::clone(&self.calls);
+                    let finished_key = key.clone();
+                    let fut = call(request);
+                    let shared = async move {
+                        let result = fut.await;
+                        calls_ref.lock().unwrap_or_else(|e| e.into_inner()).remove(&finished_key);
+                        result
+                    }.boxed().shared();
+
+                    if let Some(weak) = shared.downgrade() {
+                        calls.insert(key.clone(), weak);
+                    }
+                    (shared, "upstream")
+                }
+            }
+        };
+
+        self.publisher.spawn_publish(format!("single_flight,backend={},method={},result={} count=1", self.backend, self.method, result));
+
+        // Any waiter drives the call, it continues when the first caller goes away
+        Waiter { shared, calls: Arc::clone(&self.calls), key }.await
+    }
+}
+
+// Removes the call once no waiter is left, e.g. when all callers disconnected before it finished
+struct Waiter<T> {
+    shared: Shared<CallFuture<T>>,
+    calls: Calls<T>,
+    key: CallKey,
+}
+
+impl<T: Clone> Future for Waiter<T> {
+    type Output = Result<T, Status>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.shared).poll(cx)
+    }
+}
+
+impl<T> Drop for Waiter<T> {
+    fn drop(&mut self) {
+        // Locked first, so no caller joins the call in between
+        let mut calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        if self.shared.strong_count() != Some(1) {
+            return;
+        }
+        if calls.get(&self.key).and_then(WeakShared::upgrade).is_some_and(|call| call.ptr_eq(&self.shared)) {
+            calls.remove(&self.key);
+        }
+    }
+}
+
+impl<T> fmt::Debug for SingleFlight<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleFlight").field("backend", &self.backend).field("method", &self.method).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use futures::future;
+    use reqwest::Client;
+
+    fn single_flight() -> SingleFlight<i64> {
+        let url = Arc::new("http://127.0.0.1:9".to_owned());
+        let publisher = MetricsPublisher::new(Arc::new(Client::new()), Arc::default(), url, Arc::default(), Arc::default());
+        SingleFlight::new("product", "get_product", Arc::new(publisher))
+    }
+
+    fn in_flight(flight: &SingleFlight<i64>) -> usize {
+        flight.calls.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn identical_calls_share_one_result() {
+        let flight = single_flight();
+
+        let (sender, receiver) = oneshot::channel();
+        let first = flight.call("a".to_owned(), |_| receiver.map(|result| Ok(result.unwrap_or_default())));
+        let second = flight.call("a".to_owned(), |_| future::ready(Ok(2)));
+        // Sent once both callers are waiting
+        let send = async move { sender.send(1) };
+
+        let (first, second, _) = future::join3(first, second, send).await;
+        assert_eq!((first.unwrap(), second.unwrap()), (1, 1));
+        assert_eq!(in_flight(&flight), 0);
+    }
+
+    #[tokio::test]
+    async fn calls_are_dropped_with_their_last_waiter() {
+        let flight = single_flight();
+
+        let mut first = Box::pin(flight.call("a".to_owned(), |_| future::pending()));
+        let mut second = Box::pin(flight.call("a".to_owned(), |_| future::pending()));
+        assert!((&mut first).now_or_never().is_none());
+        assert!((&mut second).now_or_never().is_none());
+        assert_eq!(in_flight(&flight), 1);
+
+        drop(first);
+        assert_eq!(in_flight(&flight), 1);
+
+        drop(second);
+        assert_eq!(in_flight(&flight), 0);
+    }
+}
@@ -0,0 +1,432 @@
+//! REST routes of `google.api.http` annotations and JSON calls of RPCs only known through their descriptors,
+//! transcoded to gRPC calls
+
+use crate::error::Error;
+use crate::services::reflection::reflect_descriptors;
+use crate::services::{compressed_client, BackendChannel, BackendConfig, GrpcChannel, SharedChannel};
+use actix_web::http::Method;
+use itertools::Itertools;
+use log::{info, warn};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, ExtensionDescriptor, Kind, MessageDescriptor, MethodDescriptor, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tonic::client::Grpc;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::body::BoxBody;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::codegen::{http, Service};
+use tonic::Status;
+
+/// Descriptor set of `proto/*.proto` written by `build.rs`
+const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/gateway_descriptor.bin"));
+
+const HTTP_RULE_EXTENSION: &str = "google.api.http";
+
+compressed_client!(Grpc<GrpcChannel>);
+
+/// REST route of one `google.api.http` binding of an RPC.
+#[derive(Debug, Clone)]
+pub struct HttpBinding {
+    pub method: Method,
+    /// Path template of the annotation, e.g. `/orders/{order_id}`
+    pub template: String,
+    /// Actix path pattern of the template, its variables are named `p0`, `p1`, ...
+    pub pattern: String,
+    /// Variable name and request field path of every path variable, e.g. `("p0", "order_id")`
+    pub path_fields: Vec<(String, String)>,
+    /// `*` maps the body to the whole request message, a field name to that field, `None` means no body
+    pub body: Option<String>,
+    /// Field of the response message sent as body instead of the whole message
+    pub response_body: Option<String>,
+    /// Name of the backend serving the RPC
+    pub backend: String,
+    pub rpc: MethodDescriptor,
+}
+
+/// RPC together with the name of the backend serving it
+#[derive(Debug, Clone)]
+pub struct BackendRpc {
+    pub backend: String,
+    pub rpc: MethodDescriptor,
+}
+
+/// Where the descriptors of the RPCs of a backend come from
+#[derive(Debug, Clone)]
+pub enum DescriptorSource {
+    /// `proto/*.proto` compiled into the gateway, the backend serves the package named like the backend
+    Compiled,
+    /// Descriptor set file, e.g. written by `protoc --include_imports --descriptor_set_out`
+    File(PathBuf),
+    /// gRPC server reflection of the backend, asked once at startup
+    Reflection,
+}
+
+// Collects the bindings of all unary RPCs with a `google.api.http` option, additional bindings included
+fn http_bindings(pool: &DescriptorPool) -> Result<Vec<HttpBinding>, Error> {
+    let Some(extension) = pool.get_extension_by_name(HTTP_RULE_EXTENSION) else {
+        return Ok(Vec::new());
+    };
+
+    let mut bindings = Vec::new();
+    for rpc in pool.services().flat_map(|service| service.methods().collect::<Vec<_>>()) {
+        let rules = http_rules(&rpc, &extension);
+        if rules.is_empty() {
+            continue;
+        }
+        if rpc.is_client_streaming() || rpc.is_server_streaming() {
+            warn!("skipping google.api.http of streaming rpc {}", rpc.full_name());
+            continue;
+        }
+        for rule in rules {
+            bindings.push(http_binding(&rpc, &rule)?);
+        }
+    }
+    Ok(bindings)
+}
+
+fn http_rules(rpc: &MethodDescriptor, extension: &ExtensionDescriptor) -> Vec<DynamicMessage> {
+    let options = rpc.options();
+    if !options.has_extension(extension) {
+        return Vec::new();
+    }
+    let Value::Message(rule) = options.get_extension(extension).into_owned() else {
+        return Vec::new();
+    };
+
+    let additional = match rule.get_field_by_name("additional_bindings").as_deref() {
+        Some(Value::List(rules)) => rules.iter().filter_map(|r| r.as_message().cloned()).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut rules = vec![rule];
+    rules.extend(additional);
+    rules
+}
+
+fn http_binding(rpc: &MethodDescriptor, rule: &DynamicMessage) -> Result<HttpBinding, Error> {
+    let string = |message: &DynamicMessage, name: &str| message.get_field_by_name(name)
+        .and_then(|v| v.as_str().map(str::to_owned))
+        .filter(|s| !s.is_empty());
+    let invalid = |message: String| Error::Descriptor(format!("google.api.http of {}: {}", rpc.full_name(), message));
+
+    let pattern = [("get", Method::GET), ("put", Method::PUT), ("post", Method::POST), ("delete", Method::DELETE), ("patch", Method::PATCH)]
+        .into_iter()
+        .find_map(|(name, method)| string(rule, name).map(|template| (method, template)));
+    let custom = rule.get_field_by_name("custom")
+        .and_then(|v| v.as_message().cloned())
+        .and_then(|custom| Some((string(&custom, "kind")?, string(&custom, "path")?)));
+
+    let (method, template) = match (pattern, custom) {
+        (Some(pattern), _) => pattern,
+        (None, Some((kind, template))) => (Method::from_bytes(kind.as_bytes()).map_err(|_| invalid(format!("invalid method {}", kind)))?, template),
+        (None, None) => return Err(invalid("missing path template".to_owned())),
+    };
+
+    let (pattern, path_fields) = path_pattern(&template).ok_or_else(|| invalid(format!("invalid path template {}", template)))?;
+    for (_, field) in &path_fields {
+        if field_path(&rpc.input(), field).is_none() {
+            return Err(invalid(format!("unknown field {} in {}", field, template)));
+        }
+    }
+
+    Ok(HttpBinding {
+        method,
+        template,
+        pattern,
+        path_fields,
+        body: string(rule, "body"),
+        response_body: string(rule, "response_body"),
+        backend: rpc.parent_service().package_name().to_owned(),
+        rpc: rpc.clone(),
+    })
+}
+
+// Turns `/orders/{order_id}` into `/orders/{p0}` and `{name=shelves/*}` into `{p0:shelves/[^/]+}`
+fn path_pattern(template: &str) -> Option<(String, Vec<(String, String)>)> {
+    let mut pattern = String::new();
+    let mut path_fields = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        pattern.push_str(&rest[..start]);
+
+        let name = format!("p{}", path_fields.len());
+        let (field, segments) = rest[start + 1..end].split_once('=').unwrap_or((&rest[start + 1..end], "*"));
+        if segments == "*" {
+            pattern.push_str(&format!("{{{}}}", name));
+        } else {
+            let regex = segments.split('/').map(|segment| match segment {
+                "*" => "[^/]+".to_owned(),
+                "**" => ".*".to_owned(),
+                literal => regex::escape(literal),
+            }).join("/");
+            pattern.push_str(&format!("{{{}:{}}}", name, regex));
+        }
+        path_fields.push((name, field.to_owned()));
+
+        rest = &rest[end + 1..];
+    }
+    pattern.push_str(rest);
+
+    Some((pattern, path_fields))
+}
+
+/// Resolves a field path like `product.id` to the JSON names of its fields and the kind of the last one.
+pub fn field_path(message: &MessageDescriptor, path: &str) -> Option<(Vec<String>, Kind, bool)> {
+    let mut message = message.clone();
+    let mut names = Vec::new();
+    let mut segments = path.split('.').peekable();
+
+    while let Some(segment) = segments.next() {
+        let field = message.get_field_by_name(segment).or_else(|| message.get_field_by_json_name(segment))?;
+        names.push(field.json_name().to_owned());
+        if segments.peek().is_none() {
+            return Some((names, field.kind(), field.is_list()));
+        }
+        message = match field.kind() {
+            Kind::Message(nested) if !field.is_list() && !field.is_map() => nested,
+            _ => return None,
+        };
+    }
+    None
+}
+
+/// Calls RPCs only known through their descriptors, backends from the config file are reached this way only.
+#[derive(Debug, Clone)]
+pub struct TranscodingService {
+    backends: Arc<HashMap<String, TranscodedBackend>>,
+}
+
+#[derive(Debug)]
+struct TranscodedBackend {
+    channel: BackendChannel,
+    descriptors: DescriptorPool,
+    /// Not compiled into the gateway, all of its services are exposed
+    dynamic: bool,
+}
+
+impl TranscodingService {
+    /// The channels are shared with the compiled clients of the backends.
+    pub async fn new(backends: Vec<(BackendConfig, SharedChannel, DescriptorSource)>) -> Result<Self, Error> {
+        let compiled = DescriptorPool::decode(FILE_DESCRIPTOR_SET).map_err(|e| Error::Descriptor(e.to_string()))?;
+
+        let mut transcoded = HashMap::new();
+        for (backend, channel, source) in backends {
+            if transcoded.contains_key(&backend.name) {
+                return Err(Error::Descriptor(format!("backend {} is configured twice", backend.name)));
+            }
+
+            let channel = BackendChannel::new(&backend, channel);
+            let descriptors = match &source {
+                DescriptorSource::Compiled => compiled.clone(),
+                DescriptorSource::File(path) => DescriptorPool::decode(fs::read(path)?.as_slice())
+                    .map_err(|e| Error::Descriptor(format!("{}: {}", path.display(), e)))?,
+                DescriptorSource::Reflection => reflect_descriptors(&backend.name, channel.current()).await?,
+            };
+            info!("{} backend serves {}", backend.name, descriptors.services().map(|s| s.full_name().to_owned()).join(", "));
+
+            transcoded.insert(backend.name.clone(), TranscodedBackend {
+                channel,
+                descriptors,
+                dynamic: !matches!(source, DescriptorSource::Compiled),
+            });
+        }
+
+        Ok(Self { backends: Arc::new(transcoded) })
+    }
+
+    /// Bindings of the annotated RPCs of every backend.
+    pub fn http_bindings(&self) -> Result<Vec<HttpBinding>, Error> {
+        let mut bindings = Vec::new();
+        for (name, backend) in self.backends.iter().sorted_by_key(|(name, _)| name.as_str()) {
+            for mut binding in http_bindings(&backend.descriptors)? {
+                if !backend.dynamic && binding.rpc.parent_service().package_name() != name {
+                    continue;
+                }
+                binding.backend = name.clone();
+                bindings.push(binding);
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Every RPC of every backend, compiled backends only serve the package named like them.
+    pub fn rpcs(&self) -> Vec<BackendRpc> {
+        let mut rpcs = Vec::new();
+        for (name, backend) in self.backends.iter().sorted_by_key(|(name, _)| name.as_str()) {
+            let services = backend.descriptors.services()
+                .filter(|service| backend.dynamic || service.package_name() == name);
+            for service in services {
+                rpcs.extend(service.methods().map(|rpc| BackendRpc { backend: name.clone(), rpc }));
+            }
+        }
+        rpcs
+    }
+
+    /// Unary RPC `method` of the fully qualified `service` of a dynamic backend.
+    // The binding keeps the iterator's borrow of `service` from outliving it
+    #[allow(clippy::let_and_return)]
+    pub fn dynamic_rpc(&self, backend: &str, service: &str, method: &str) -> Option<MethodDescriptor> {
+        let backend = self.backends.get(backend).filter(|b| b.dynamic)?;
+        let service = backend.descriptors.get_service_by_name(service)?;
+        let rpc = service.methods().find(|rpc| rpc.name() == method && !rpc.is_client_streaming() && !rpc.is_server_streaming());
+        rpc
+    }
+
+    pub async fn call(&self, backend: &str, rpc: &MethodDescriptor, request: DynamicMessage) -> Result<DynamicMessage, Error> {
+        let input = format!("{} failed", rpc.full_name());
+        let service = rpc.parent_service();
+
+        let backend = self.backends.get(backend)
+            .ok_or_else(|| Error::GrpcStatus { input: input.clone(), status: Status::unimplemented(format!("unknown backend {}", backend)) })?;
+        let mut grpc = backend.channel.client(Grpc::new);
+
+        grpc.ready().await
+            .map_err(|e| Error::GrpcStatus { input: input.clone(), status: Status::unknown(format!("Service was not ready: {}", e)) })?;
+
+        let path = PathAndQuery::try_from(format!("/{}/{}", service.full_name(), rpc.name()))
+            .map_err(|e| Error::GrpcStatus { input: input.clone(), status: Status::internal(e.to_string()) })?;
+
+        grpc.unary(tonic::Request::new(request), path, DynamicCodec(rpc.output())).await
+            .map(tonic::Response::into_inner)
+            .map_err(|status| Error::GrpcStatus { input, status })
+    }
+
+    /// Sends a gRPC request as it is to the backend, gRPC-Web calls are forwarded this way.
+    pub async fn forward(&self, backend: &str, request: http::Request<BoxBody>) -> Result<http::Response<BoxBody>, Status> {
+        let backend = self.backends.get(backend)
+            .ok_or_else(|| Status::unimplemented(format!("unknown backend {}", backend)))?;
+        let mut channel = backend.channel.current();
+
+        std::future::poll_fn(|cx| Service::<http::Request<BoxBody>>::poll_ready(&mut channel, cx)).await
+            .map_err(|e| Status::unavailable(format!("Service was not ready: {}", e)))?;
+
+        channel.call(request).await.map_err(|e| Status::unavailable(e.to_string()))
+    }
+}
+
+/// Codec of messages which are only known through their descriptor, encodes any message and decodes the given type.
+#[derive(Debug, Clone)]
+struct DynamicCodec(MessageDescriptor);
+
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicCodec;
+    type Decoder = DynamicCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        self.clone()
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        self.clone()
+    }
+}
+
+impl Encoder for DynamicCodec {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        item.encode(dst).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+impl Decoder for DynamicCodec {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        DynamicMessage::decode(self.0.clone(), src)
+            .map(Some)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::intercept;
+    use std::sync::RwLock;
+    use tonic::transport::Channel;
+
+    fn order_list_request() -> MessageDescriptor {
+        DescriptorPool::decode(FILE_DESCRIPTOR_SET).unwrap().get_message_by_name("order.OrderListRequest").unwrap()
+    }
+
+    #[test]
+    fn simple_variables_become_named_segments() {
+        let (pattern, path_fields) = path_pattern("/orders/{order_id}/items/{item.sku_code}").unwrap();
+
+        assert_eq!(pattern, "/orders/{p0}/items/{p1}");
+        assert_eq!(path_fields, [("p0".to_owned(), "order_id".to_owned()), ("p1".to_owned(), "item.sku_code".to_owned())]);
+    }
+
+    #[test]
+    fn variables_with_segments_become_regex_segments() {
+        let (pattern, path_fields) = path_pattern("/v1/{name=shelves/*}/books").unwrap();
+
+        assert_eq!(pattern, "/v1/{p0:shelves/[^/]+}/books");
+        assert_eq!(path_fields, [("p0".to_owned(), "name".to_owned())]);
+    }
+
+    #[test]
+    fn double_wildcards_match_the_rest_of_the_path() {
+        let (pattern, _) = path_pattern("/files/{path=docs/**}").unwrap();
+
+        assert_eq!(pattern, "/files/{p0:docs/.*}");
+    }
+
+    #[test]
+    fn unclosed_variables_are_rejected() {
+        assert!(path_pattern("/orders/{order_id").is_none());
+    }
+
+    #[test]
+    fn field_paths_resolve_nested_fields() {
+        let (names, kind, repeated) = field_path(&order_list_request(), "created_after.seconds").unwrap();
+
+        assert_eq!(names, ["createdAfter", "seconds"]);
+        assert!(matches!(kind, Kind::Int64));
+        assert!(!repeated);
+    }
+
+    #[test]
+    fn field_paths_accept_json_names() {
+        let (names, _, _) = field_path(&order_list_request(), "sortBy").unwrap();
+
+        assert_eq!(names, ["sortBy"]);
+    }
+
+    #[test]
+    fn field_paths_through_scalars_are_rejected() {
+        assert!(field_path(&order_list_request(), "limit.value").is_none());
+        assert!(field_path(&order_list_request(), "unknown").is_none());
+    }
+
+    #[tokio::test]
+    async fn compiled_backends_serve_their_annotated_rpcs() {
+        let backends = ["auth", "order", "product"].into_iter().map(|name| {
+            let backend = BackendConfig { name: name.to_owned(), endpoint: "http://[::1]:50051".to_owned(), tls: None, compression: None };
+            let channel = Arc::new(RwLock::new(intercept(Channel::from_static("http://[::1]:50051").connect_lazy())));
+            (backend, channel, DescriptorSource::Compiled)
+        }).collect();
+        let service = TranscodingService::new(backends).await.unwrap();
+
+        let bindings = service.http_bindings().unwrap();
+
+        assert_eq!(bindings.len(), 1);
+        let binding = &bindings[0];
+        assert_eq!(binding.rpc.full_name(), "order.Order.GetOrder");
+        assert_eq!(binding.backend, "order");
+        assert_eq!(binding.method, Method::GET);
+        assert_eq!(binding.pattern, "/orders/{p0}/items");
+        assert_eq!(binding.path_fields, [("p0".to_owned(), "order_id".to_owned())]);
+        assert_eq!(binding.response_body.as_deref(), Some("items"));
+    }
+}
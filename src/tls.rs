@@ -0,0 +1,164 @@
+//! TLS termination with SNI certificate selection and certificate hot reload
+
+use crate::config::{watch_files, ClientAuthConfig, TlsCertificateConfig};
+use crate::error::Error;
+use log::{error, info};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Certificates {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+/// Picks the certificate by SNI server name, falling back to the default certificate.
+pub struct CertResolver {
+    configs: Vec<TlsCertificateConfig>,
+    certificates: RwLock<Certificates>,
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver").field("configs", &self.configs).finish()
+    }
+}
+
+impl CertResolver {
+    pub fn new(configs: Vec<TlsCertificateConfig>) -> Result<Self, Error> {
+        let certificates = load_certificates(&configs)?;
+
+        Ok(CertResolver {
+            configs,
+            certificates: RwLock::new(certificates),
+        })
+    }
+
+    /// Reloads all certificates, the previous ones stay in use when any of them fails to load.
+    pub fn reload(&self) {
+        match load_certificates(&self.configs) {
+            Ok(certificates) => {
+                *self.certificates.write().unwrap_or_else(|e| e.into_inner()) = certificates;
+                info!("reloaded TLS certificates");
+            }
+            Err(e) => error!("keeping previous TLS certificates, reload failed: {}", e),
+        }
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.configs.iter()
+            .flat_map(|c| [c.cert.clone(), c.key.clone()])
+            .collect()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.certificates.read().unwrap_or_else(|e| e.into_inner());
+
+        let by_name = client_hello.server_name().and_then(|name| {
+            let name = name.to_ascii_lowercase();
+            certificates.by_name.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                certificates.by_name.get(&format!("*.{}", parent))
+            })
+        });
+
+        by_name.or(certificates.default.as_ref()).cloned()
+    }
+}
+
+fn load_certificates(configs: &[TlsCertificateConfig]) -> Result<Certificates, Error> {
+    let mut certificates = Certificates::default();
+
+    for config in configs {
+        let key = Arc::new(load_certified_key(&config.cert, &config.key)?);
+        for name in &config.server_names {
+            certificates.by_name.insert(name.to_ascii_lowercase(), Arc::clone(&key));
+        }
+        // The first certificate serves clients without SNI unless another one is marked as default
+        if config.default || certificates.default.is_none() {
+            certificates.default = Some(key);
+        }
+    }
+
+    Ok(certificates)
+}
+
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|e| certificate_error(key_path, e))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| certificate_error(path, e))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| certificate_error(path, e))?;
+
+    if certs.is_empty() {
+        return Err(certificate_error(path, "no certificate found"));
+    }
+
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| certificate_error(path, e))?);
+
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| certificate_error(path, e))?
+        .ok_or_else(|| certificate_error(path, "no private key found"))
+}
+
+fn certificate_error(path: &Path, e: impl fmt::Display) -> Error {
+    Error::Certificate { path: path.display().to_string(), message: e.to_string() }
+}
+
+/// Builds the rustls config of the HTTPS listener, actix adds the `h2` and `http/1.1` ALPN protocols itself.
+pub fn server_config(resolver: Arc<CertResolver>, client_auth: Option<&ClientAuthConfig>) -> Result<ServerConfig, Error> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+
+    let config = match client_auth {
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&client_auth.ca)? {
+                roots.add(cert).map_err(|e| certificate_error(&client_auth.ca, e))?;
+            }
+
+            let mut verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            if !client_auth.required {
+                verifier = verifier.allow_unauthenticated();
+            }
+            let verifier = verifier.build()
+                .map_err(|e| certificate_error(&client_auth.ca, e))?;
+
+            builder.with_client_cert_verifier(verifier).with_cert_resolver(resolver)
+        }
+        None => builder.with_no_client_auth().with_cert_resolver(resolver),
+    };
+
+    Ok(config)
+}
+
+/// Reloads the certificates whenever one of the certificate or key files changes.
+pub fn watch_certificates(resolver: Arc<CertResolver>, interval: Duration) {
+    watch_files(resolver.paths(), interval, move || resolver.reload());
+}
@@ -0,0 +1,210 @@
+//! Request body size limits and validation of JSON payloads and query parameters
+
+use crate::error::{api_error, validation_error};
+use actix_web::dev::Payload;
+use actix_web::error::JsonPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+/// Active ISO 4217 currency codes
+const CURRENCIES: [&str; 156] = [
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT", "BGN",
+    "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD", "CDF", "CHF",
+    "CLP", "CNY", "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB",
+    "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG",
+    "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF",
+    "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA",
+    "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO",
+    "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD",
+    "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN",
+    "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX",
+    "USD", "UYU", "UZS", "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW",
+    "ZWL", "ZWG",
+];
+
+pub fn validate_currency(currency: &str) -> Result<(), ValidationError> {
+    if CURRENCIES.contains(&currency) {
+        return Ok(());
+    }
+    Err(error("currency", "must be an ISO 4217 currency code, e.g. USD"))
+}
+
+/// Passwords of new accounts need at least one letter and one digit, the length is checked separately
+pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+    let has_letter = password.chars().any(char::is_alphabetic);
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if has_letter && has_digit {
+        return Ok(());
+    }
+    Err(error("password", "must contain at least one letter and one digit"))
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
+
+/// Limits the JSON body of a resource to `limit` bytes and answers malformed bodies with a JSON error.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|e, _| {
+            let status = match &e {
+                JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                JsonPayloadError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            api_error(status, e.to_string())
+        })
+}
+
+/// JSON body extractor that validates the payload, invalid payloads get a 400 with the messages per field.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            value.validate().map_err(|e| validation_error("invalid request body", field_errors(&e)))?;
+
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+/// Query string extractor that validates the parameters, invalid parameters get a 400 with the messages per field.
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T> ValidatedQuery<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let result = web::Query::<T>::from_query(req.query_string())
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e.to_string()))
+            .and_then(|query| {
+                let query = query.into_inner();
+                query.validate().map_err(|e| validation_error("invalid query parameters", field_errors(&e)))?;
+                Ok(ValidatedQuery(query))
+            });
+
+        ready(result)
+    }
+}
+
+/// Accepts `field` or `-field` for a descending order, when `field` is one of `allowed`.
+pub fn validate_sort(sort: &str, allowed: &[&str]) -> Result<(), ValidationError> {
+    let field = sort.strip_prefix('-').unwrap_or(sort);
+    if allowed.contains(&field) {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("sort");
+    error.message = Some(Cow::Owned(format!("must be one of {}, prefixed with - for descending order", allowed.join(", "))));
+    Err(error)
+}
+
+/// Cursor and offset are two ways to pick a page, only one of them can be given.
+pub fn validate_page(cursor: Option<&String>, offset: Option<u32>) -> Result<(), ValidationError> {
+    if cursor.is_some() && offset.is_some() {
+        return Err(error("offset", "can not be combined with cursor"));
+    }
+    Ok(())
+}
+
+/// Checks that the lower bound of a range filter does not exceed the upper one.
+pub fn validate_range<T: PartialOrd>(from: Option<T>, to: Option<T>, to_field: &'static str) -> Result<(), ValidationError> {
+    match (from, to) {
+        (Some(from), Some(to)) if from > to => Err(error(to_field, "must not be less than the lower bound")),
+        _ => Ok(()),
+    }
+}
+
+pub fn field_errors(errors: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    let mut fields = BTreeMap::new();
+    collect_field_errors(errors, "", &mut fields);
+    fields
+}
+
+// Flattens nested errors into paths like `items[2].price`
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut BTreeMap<String, Vec<String>>) {
+    let path = |field: &str| if prefix.is_empty() { field.to_owned() } else { format!("{}.{}", prefix, field) };
+
+    for (field, kind) in errors.errors() {
+        match kind {
+            // Struct level checks name the offending field in the error code
+            ValidationErrorsKind::Field(errors) if *field == "__all__" => {
+                for error in errors {
+                    fields.entry(path(&error.code)).or_default().push(message(error));
+                }
+            }
+            ValidationErrorsKind::Field(errors) => {
+                fields.entry(path(field)).or_default().extend(errors.iter().map(message));
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path(field), fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}[{}]", path(field), index), fields);
+                }
+            }
+        }
+    }
+}
+
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+    match error.code.as_ref() {
+        "length" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("length must be between {} and {}", min, max),
+            (Some(min), None) => format!("length must be at least {}", min),
+            (None, Some(max)) => format!("length must be at most {}", max),
+            (None, None) => "invalid length".to_owned(),
+        },
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+            (Some(min), None) => format!("must be at least {}", min),
+            (None, Some(max)) => format!("must be at most {}", max),
+            (None, None) => "out of range".to_owned(),
+        },
+        "email" => "must be a valid email address".to_owned(),
+        code => format!("invalid value ({})", code),
+    }
+}